@@ -50,8 +50,10 @@ impl From<dust_dds::infrastructure::error::DdsError> for VodaError {
 
 #[derive(Debug, dust_dds::topic_definition::type_support::DdsType)]
 struct Video<'a> {
+    #[dust_dds(key)]
     user_id: i16,
     frame_num: i32,
+    temporal_layer: u8,
     frame: &'a [u8],
 }
 
@@ -377,6 +379,8 @@ fn create_pipeline() -> Result<gstreamer::Pipeline, VodaError> {
                     let video_sample = Video {
                         user_id: 8,
                         frame_num: i,
+                        // openh264enc encodes a single temporal layer
+                        temporal_layer: 0,
                         frame: buffer_map.as_slice(),
                     };
                     i += 1;
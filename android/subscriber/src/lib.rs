@@ -57,8 +57,10 @@ impl From<dust_dds::infrastructure::error::DdsError> for VodaError {
 
 #[derive(Debug, dust_dds::topic_definition::type_support::DdsType)]
 struct Video<'a> {
+    #[dust_dds(key)]
     user_id: i16,
     frame_num: i32,
+    temporal_layer: u8,
     frame: &'a [u8],
 }
 
@@ -0,0 +1,108 @@
+use crate::stats::StreamStatistics;
+use dust_dds::publication::data_writer::DataWriter;
+
+pub const STREAM_ALERT_TOPIC_NAME: &str = "StreamAlert";
+pub const STREAM_ALERT_TYPE_NAME: &str = "StreamAlert";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertKind {
+    /// No frame arrived within the deadline derived from the framerate. Raised
+    /// once when the stream stalls, not for every deadline period missed
+    DeadlineMissed,
+    /// A frame arrived again after the deadline was missed
+    DeadlineRecovered,
+    /// The publisher stopped asserting its liveliness
+    LivelinessLost,
+    /// A publisher that was lost is alive again
+    LivelinessRestored,
+}
+
+impl AlertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertKind::DeadlineMissed => "deadline-missed",
+            AlertKind::DeadlineRecovered => "deadline-recovered",
+            AlertKind::LivelinessLost => "liveliness-lost",
+            AlertKind::LivelinessRestored => "liveliness-restored",
+        }
+    }
+}
+
+/// Alert about a stalled or lost stream, published by the subscribers so
+/// that monitoring tools can collect them from all hosts.
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct StreamAlert {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    /// Host of the subscriber raising the alert
+    #[dust_dds(key)]
+    pub host: String,
+    pub kind: String,
+    pub deadline_missed: u64,
+    pub liveliness_lost: u64,
+}
+
+/// Raises alerts by printing them, publishing them on the alert topic and
+/// running the optional alert command. The command is run by `sh -c` with the
+/// alert in the `VODA_ALERT`, `VODA_USER_ID`, `VODA_DEADLINE_MISSED` and
+/// `VODA_LIVELINESS_LOST` environment variables.
+pub struct Alerts {
+    host: String,
+    writer: DataWriter<StreamAlert>,
+    command: Option<String>,
+}
+
+impl Alerts {
+    pub fn new(writer: DataWriter<StreamAlert>, command: Option<String>) -> Self {
+        Self {
+            host: gethostname::gethostname().to_string_lossy().into_owned(),
+            writer,
+            command,
+        }
+    }
+
+    pub fn raise(&self, kind: AlertKind, statistics: &StreamStatistics) {
+        println!(
+            "ALERT user_id {}: {} (deadline missed {} times, liveliness lost {} times)",
+            statistics.user_id(),
+            kind.as_str(),
+            statistics.deadline_missed(),
+            statistics.liveliness_lost()
+        );
+
+        let alert = StreamAlert {
+            user_id: statistics.user_id(),
+            host: self.host.clone(),
+            kind: kind.as_str().to_string(),
+            deadline_missed: statistics.deadline_missed(),
+            liveliness_lost: statistics.liveliness_lost(),
+        };
+        if let Err(e) = self.writer.write(&alert, None) {
+            println!("Writing alert failed: {:?}", e);
+        }
+
+        if let Some(command) = &self.command {
+            let child = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("VODA_ALERT", kind.as_str())
+                .env("VODA_USER_ID", statistics.user_id().to_string())
+                .env(
+                    "VODA_DEADLINE_MISSED",
+                    statistics.deadline_missed().to_string(),
+                )
+                .env(
+                    "VODA_LIVELINESS_LOST",
+                    statistics.liveliness_lost().to_string(),
+                )
+                .spawn();
+            match child {
+                // Reap the command without blocking the listener
+                Ok(mut child) => {
+                    std::thread::spawn(move || child.wait());
+                }
+                Err(e) => println!("Running alert command {:?} failed: {}", command, e),
+            }
+        }
+    }
+}
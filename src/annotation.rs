@@ -0,0 +1,291 @@
+use crate::pipeline::frame_tag;
+use dust_dds::subscription::{
+    data_reader::DataReader,
+    data_reader_listener::DataReaderListener,
+    sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+};
+use gstreamer::prelude::*;
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+pub const ANNOTATION_TOPIC_NAME: &str = "Annotation";
+pub const ANNOTATION_TYPE_NAME: &str = "Annotation";
+
+/// Annotations kept per stream and producer to be matched with the frames
+const HISTORY_DEPTH: usize = 64;
+/// Number of frames an annotation stays shown, so that producers analyzing
+/// only some of the frames do not make the overlay flicker
+const MAX_AGE_FRAMES: i32 = 25;
+
+/// Colors of the producers, picked by their names
+const COLORS: &[&str] = &[
+    "#ff3b30", "#34c759", "#007aff", "#ffcc00", "#af52de", "#5ac8fa",
+];
+
+/// Rectangle of a frame, in fractions of the frame size
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub left: f64,
+    pub top: f64,
+    pub width: f64,
+    pub height: f64,
+    pub label: String,
+}
+
+/// Regions a `producer`, e.g. a detector, found in frame `frame_num` of the
+/// stream of `user_id`. Entry `i` of the vectors describes region `i`.
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct Annotation {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    #[dust_dds(key)]
+    pub producer: String,
+    pub frame_num: i32,
+    pub lefts: Vec<f64>,
+    pub tops: Vec<f64>,
+    pub widths: Vec<f64>,
+    pub heights: Vec<f64>,
+    pub labels: Vec<String>,
+}
+
+impl Annotation {
+    pub fn new(user_id: i16, producer: &str, frame_num: i32, regions: &[Region]) -> Self {
+        Self {
+            user_id,
+            producer: producer.to_string(),
+            frame_num,
+            lefts: regions.iter().map(|region| region.left).collect(),
+            tops: regions.iter().map(|region| region.top).collect(),
+            widths: regions.iter().map(|region| region.width).collect(),
+            heights: regions.iter().map(|region| region.height).collect(),
+            labels: regions.iter().map(|region| region.label.clone()).collect(),
+        }
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = Region> + '_ {
+        self.lefts
+            .iter()
+            .zip(&self.tops)
+            .zip(&self.widths)
+            .zip(&self.heights)
+            .enumerate()
+            .map(|(i, (((&left, &top), &width), &height))| Region {
+                left,
+                top,
+                width,
+                height,
+                label: self.labels.get(i).cloned().unwrap_or_default(),
+            })
+    }
+}
+
+/// Latest annotations of every stream and producer, shared between the
+/// annotation listener and the overlay
+#[derive(Clone, Default)]
+pub struct AnnotationCache(Arc<Mutex<HashMap<(i16, String), VecDeque<Annotation>>>>);
+
+impl AnnotationCache {
+    fn insert(&self, annotation: Annotation) {
+        let mut annotations = self.0.lock().expect("annotation lock not poisoned");
+        let history = annotations
+            .entry((annotation.user_id, annotation.producer.clone()))
+            .or_default();
+        if history.len() == HISTORY_DEPTH {
+            history.pop_front();
+        }
+        history.push_back(annotation);
+    }
+
+    /// Names of all producers seen so far
+    pub fn producers(&self) -> BTreeSet<String> {
+        self.0
+            .lock()
+            .expect("annotation lock not poisoned")
+            .keys()
+            .map(|(_, producer)| producer.clone())
+            .collect()
+    }
+
+    /// Annotation of every producer for frame `frame_num`, or the latest one
+    /// of the frames before it, sorted by producer
+    fn matching(&self, user_id: i16, frame_num: i32) -> Vec<Annotation> {
+        let annotations = self.0.lock().expect("annotation lock not poisoned");
+        let mut matching: Vec<_> = annotations
+            .iter()
+            .filter(|((annotated_user_id, _), _)| *annotated_user_id == user_id)
+            .filter_map(|(_, history)| {
+                history
+                    .iter()
+                    .filter(|annotation| {
+                        (0..MAX_AGE_FRAMES).contains(&frame_num.wrapping_sub(annotation.frame_num))
+                    })
+                    .max_by_key(|annotation| annotation.frame_num.wrapping_sub(frame_num))
+                    .cloned()
+            })
+            .collect();
+        matching.sort_by(|a, b| a.producer.cmp(&b.producer));
+        matching
+    }
+}
+
+/// Stores all received annotations in the cache
+pub struct AnnotationListener {
+    pub cache: AnnotationCache,
+}
+
+impl<'a> DataReaderListener<'a> for AnnotationListener {
+    type Foo = Annotation;
+
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
+            for sample in samples {
+                if let Ok(annotation) = sample.data() {
+                    self.cache.insert(annotation);
+                }
+            }
+        }
+    }
+}
+
+/// Draws the annotations of the frame passing an `rsvgoverlay`. The frame is
+/// identified by the tag its buffer got when it was pushed into the appsrc,
+/// see `pipeline::frame_tag`, so that the annotations stay in sync with the
+/// frames regardless of the decoding delay.
+#[derive(Clone)]
+pub struct AnnotationOverlay {
+    cache: AnnotationCache,
+    hidden: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl AnnotationOverlay {
+    /// The annotations of the `hidden` producers are not drawn
+    pub fn new(cache: AnnotationCache, hidden: Vec<String>) -> Self {
+        Self {
+            cache,
+            hidden: Arc::new(Mutex::new(hidden.into_iter().collect())),
+        }
+    }
+
+    /// Shows the annotations of a hidden producer and hides those of a shown
+    /// one. Returns whether they are shown now.
+    pub fn toggle(&self, producer: &str) -> bool {
+        let mut hidden = self.hidden.lock().expect("hidden lock not poisoned");
+        if hidden.remove(producer) {
+            true
+        } else {
+            hidden.insert(producer.to_string());
+            false
+        }
+    }
+
+    /// Known producers and whether their annotations are shown
+    pub fn producers(&self) -> Vec<(String, bool)> {
+        let hidden = self.hidden.lock().expect("hidden lock not poisoned");
+        self.cache
+            .producers()
+            .into_iter()
+            .map(|producer| {
+                let shown = !hidden.contains(&producer);
+                (producer, shown)
+            })
+            .collect()
+    }
+
+    /// Updates the image of the `rsvgoverlay` for every frame reaching it
+    pub fn attach(&self, rsvgoverlay: &gstreamer::Element) {
+        let overlay = self.clone();
+        let element = rsvgoverlay.downgrade();
+        let pad = rsvgoverlay
+            .static_pad("sink")
+            .expect("rsvgoverlay has sink pad");
+        pad.add_probe(gstreamer::PadProbeType::BUFFER, move |pad, info| {
+            let Some(gstreamer::PadProbeData::Buffer(buffer)) = &info.data else {
+                return gstreamer::PadProbeReturn::Ok;
+            };
+            let size = pad.current_caps().and_then(|caps| {
+                let structure = caps.structure(0)?;
+                Some((
+                    structure.get::<i32>("width").ok()?,
+                    structure.get::<i32>("height").ok()?,
+                ))
+            });
+            let annotations = match frame_tag(buffer) {
+                Some((user_id, frame_num)) => overlay.visible(user_id, frame_num),
+                None => Vec::new(),
+            };
+            let (width, height) = size.unwrap_or((1, 1));
+            if let Some(element) = element.upgrade() {
+                element.set_property("data", svg(&annotations, width, height));
+            }
+            gstreamer::PadProbeReturn::Ok
+        });
+    }
+
+    fn visible(&self, user_id: i16, frame_num: i32) -> Vec<Annotation> {
+        let hidden = self.hidden.lock().expect("hidden lock not poisoned");
+        self.cache
+            .matching(user_id, frame_num)
+            .into_iter()
+            .filter(|annotation| !hidden.contains(&annotation.producer))
+            .collect()
+    }
+}
+
+/// SVG image of the frame size drawing the regions of the `annotations`,
+/// every producer in its own color and above the producers before it
+fn svg(annotations: &[Annotation], width: i32, height: i32) -> String {
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}">"#,
+        width, height
+    );
+    for annotation in annotations {
+        let color = COLORS[producer_index(&annotation.producer) % COLORS.len()];
+        for region in annotation.regions() {
+            let x = region.left * width as f64;
+            let y = region.top * height as f64;
+            let _ = write!(
+                svg,
+                r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="none" stroke="{}" stroke-width="2"/>"#,
+                x,
+                y,
+                region.width * width as f64,
+                region.height * height as f64,
+                color
+            );
+            if !region.label.is_empty() {
+                let _ = write!(
+                    svg,
+                    r#"<text x="{:.1}" y="{:.1}" fill="{}" font-family="Sans" font-size="14">{}</text>"#,
+                    x + 2.0,
+                    (y - 4.0).max(14.0),
+                    color,
+                    escape(&region.label)
+                );
+            }
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Stable index of a producer, so that it keeps its color
+fn producer_index(producer: &str) -> usize {
+    producer.bytes().fold(0usize, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as usize)
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,361 @@
+use crate::{error::Error, ptz::PtzPosition, simulcast::SimulcastLayer, video::VIDEO_TOPIC_NAME};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf};
+
+/// Options shared by all binaries. They override the values of the
+/// configuration file and can also be given as environment variables.
+#[derive(clap::Args)]
+pub struct ConfigArgs {
+    /// Configuration file in TOML format
+    #[arg(long, env = "VODA_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Print the effective configuration and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// DDS domain
+    #[arg(long, env = "VODA_DOMAIN_ID")]
+    pub domain_id: Option<i32>,
+
+    /// Name of the video topic
+    #[arg(long, env = "VODA_VIDEO_TOPIC")]
+    pub video_topic: Option<String>,
+
+    /// Name of the QoS profile of the configuration file to use
+    #[arg(long, env = "VODA_QOS_PROFILE")]
+    pub qos_profile: Option<String>,
+
+    /// Name of the codec preset of the configuration file to use
+    #[arg(long, env = "VODA_CODEC_PRESET")]
+    pub codec_preset: Option<String>,
+
+    /// Expected framerate of the streams. Publishers offer and subscribers
+    /// request a deadline and liveliness derived from it
+    #[arg(long, env = "VODA_FRAMERATE")]
+    pub framerate: Option<u32>,
+
+    /// Room to join. Can be given several times, subscribers may use
+    /// wildcards, e.g. `building-a/*`
+    #[arg(long = "room", env = "VODA_ROOMS", value_delimiter = ',')]
+    pub rooms: Vec<String>,
+
+    /// H.264 encoder to use. Can be given several times to define a
+    /// preference list. Defaults to openh264enc, x264enc, avenc_h264
+    #[arg(long = "encoder", env = "VODA_ENCODERS", value_delimiter = ',')]
+    pub encoders: Vec<String>,
+
+    /// H.264 decoder to use. Can be given several times to define a
+    /// preference list. Defaults to openh264dec, avdec_h264
+    #[arg(long = "decoder", env = "VODA_DECODERS", value_delimiter = ',')]
+    pub decoders: Vec<String>,
+
+    /// Encoder bitrate in bit/s
+    #[arg(long, env = "VODA_BITRATE")]
+    pub bitrate: Option<u32>,
+
+    /// GStreamer element capturing the camera
+    #[arg(long, env = "VODA_SOURCE")]
+    pub source: Option<String>,
+
+    /// GStreamer element showing the video
+    #[arg(long, env = "VODA_SINK")]
+    pub sink: Option<String>,
+}
+
+/// Effective configuration: defaults, overridden by the configuration file,
+/// overridden by environment variables and command line options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub domain_id: i32,
+    pub video_topic: String,
+    /// Selects one of `qos_profiles` as `qos`
+    pub qos_profile: Option<String>,
+    /// Selects one of `codec_presets` as `codec`
+    pub codec_preset: Option<String>,
+    pub qos: QosProfile,
+    pub codec: CodecPreset,
+    pub publisher: PublisherConfig,
+    pub subscriber: SubscriberConfig,
+    pub qos_profiles: BTreeMap<String, QosProfile>,
+    pub codec_presets: BTreeMap<String, CodecPreset>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QosProfile {
+    pub ownership_strength: Option<i32>,
+    pub exclusive_ownership: bool,
+    pub rooms: Vec<String>,
+    /// Expected framerate for the deadline and liveliness monitoring. Readers
+    /// with a framerate only match writers offering the same or a higher one
+    pub framerate: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodecPreset {
+    /// Empty for the default preference list
+    pub encoders: Vec<String>,
+    /// Empty for the default preference list
+    pub decoders: Vec<String>,
+    pub bitrate: u32,
+}
+
+impl Default for CodecPreset {
+    fn default() -> Self {
+        Self {
+            encoders: Vec::new(),
+            decoders: Vec::new(),
+            bitrate: 1_280_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PublisherConfig {
+    pub user_id: i16,
+    /// Name announced in the stream info, defaults to `Camera <user_id>`
+    pub display_name: Option<String>,
+    /// Number of frames protected by one parity sample, without it no parity
+    /// is sent. A single lost frame per group can be recovered, in time to be
+    /// shown if the playout delay of the subscriber covers the group
+    pub fec_group_size: Option<u32>,
+    /// Split the frames into samples of at most this many bytes. Without it
+    /// every frame is sent as one sample
+    pub max_fragment_size: Option<usize>,
+    /// Bandwidth limit in bit/s, unlimited without it. A frame is written at
+    /// once, so frames larger than a 20 ms burst are only spread out when
+    /// `max_fragment_size` is set
+    pub max_bitrate: Option<u32>,
+    /// Number of frames waiting to be written before delta frames are dropped
+    pub send_queue_capacity: usize,
+    /// Host names of the subscribers allowed to control the camera. Remote
+    /// control is disabled without any. This is no access control: the host
+    /// name is put into the requests by the requester itself and not
+    /// verified, so any participant of the domain can claim to be an
+    /// operator. It only keeps other hosts from operating the camera by
+    /// mistake, untrusted participants have to be kept out of the domain
+    pub control_operators: Vec<String>,
+    /// Host names of the clients allowed to request snapshots. Snapshots are
+    /// disabled without any. Like `control_operators` the host names are not
+    /// verified
+    pub snapshot_operators: Vec<String>,
+    /// Named views of the digital PTZ, operators can add more while running
+    pub ptz_presets: BTreeMap<String, PtzPosition>,
+    /// Additional layers published besides the main stream, from the largest
+    /// to the smallest
+    pub simulcast_layers: Vec<SimulcastLayer>,
+    /// Temporal layers of the main stream. With 2 or 3 subscribers can follow
+    /// it at 1/2 or 1/4 of the framerate. The layers are built from B frames,
+    /// so the stream is then encoded by x264enc in the main profile, whatever
+    /// the encoder preference, and delayed by up to 3 frames
+    pub temporal_layers: u8,
+    pub source: String,
+    pub sink: String,
+}
+
+impl Default for PublisherConfig {
+    fn default() -> Self {
+        Self {
+            user_id: 8,
+            display_name: None,
+            fec_group_size: None,
+            max_fragment_size: None,
+            max_bitrate: None,
+            send_queue_capacity: 8,
+            control_operators: Vec::new(),
+            snapshot_operators: Vec::new(),
+            ptz_presets: BTreeMap::new(),
+            simulcast_layers: Vec::new(),
+            temporal_layers: 1,
+            source: "autovideosrc".to_string(),
+            sink: "autovideosink".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubscriberConfig {
+    /// Empty to show all streams
+    pub user_ids: Vec<i16>,
+    pub keyframes_only: bool,
+    pub minimum_separation_ms: Option<u64>,
+    pub stats_overlay: bool,
+    /// Delay of the jitter buffer. Without it, frames are shown on arrival and
+    /// lost frames are not recovered from the parity
+    pub playout_delay_ms: Option<u64>,
+    /// Time to wait for the missing fragments of a frame
+    pub fragment_timeout_ms: u64,
+    /// Shell command run for every alert about a stalled or lost stream
+    pub alert_command: Option<String>,
+    /// Queue between the DDS listener and the decoding worker of a stream
+    pub stream_queue: StreamQueueConfig,
+    /// Overrides of `stream_queue` by `user_id`
+    pub stream_queues: BTreeMap<String, StreamQueueConfig>,
+    /// Simulcast layer shown, 0 is the main stream
+    pub layer: usize,
+    /// Choose the layer from the viewport height and the decoding performance
+    /// instead
+    pub auto_layer: bool,
+    /// Height in pixels at which the streams are shown, e.g. of a mosaic tile
+    pub viewport_height: Option<u32>,
+    /// Only decode the temporal layers up to this one, 0 being the base
+    /// layer. All layers are decoded without it
+    pub max_temporal_layer: Option<u8>,
+    /// Draw the regions of the annotation topic on the frames, requires
+    /// rsvgoverlay
+    pub annotations: bool,
+    /// Producers whose annotations are not drawn until toggled
+    pub hidden_annotation_producers: Vec<String>,
+    /// Frame processors analyzing the decoded frames, e.g. `motion`. Their
+    /// annotations and events are published in the rooms of the subscriber
+    pub processors: Vec<String>,
+    pub sink: String,
+}
+
+/// What the DDS listener does with a frame when the queue of its stream is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    /// Drop the frame, the decoder recovers with the next keyframe
+    #[default]
+    Drop,
+    /// Wait for the worker, which delays the frames of all streams
+    Block,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamQueueConfig {
+    /// Number of frames waiting to be decoded
+    pub capacity: usize,
+    pub overflow: Overflow,
+}
+
+impl Default for StreamQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 16,
+            overflow: Overflow::Drop,
+        }
+    }
+}
+
+impl Default for SubscriberConfig {
+    fn default() -> Self {
+        Self {
+            user_ids: Vec::new(),
+            keyframes_only: false,
+            minimum_separation_ms: None,
+            stats_overlay: false,
+            playout_delay_ms: None,
+            fragment_timeout_ms: 500,
+            alert_command: None,
+            stream_queue: StreamQueueConfig::default(),
+            stream_queues: BTreeMap::new(),
+            layer: 0,
+            auto_layer: false,
+            viewport_height: None,
+            max_temporal_layer: None,
+            annotations: false,
+            hidden_annotation_producers: Vec::new(),
+            processors: Vec::new(),
+            sink: "autovideosink".to_string(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            domain_id: 0,
+            video_topic: VIDEO_TOPIC_NAME.to_string(),
+            qos_profile: None,
+            codec_preset: None,
+            qos: QosProfile::default(),
+            codec: CodecPreset::default(),
+            publisher: PublisherConfig::default(),
+            subscriber: SubscriberConfig::default(),
+            qos_profiles: BTreeMap::new(),
+            codec_presets: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration file, if any, and applies the overrides of
+    /// `args`. Selected QoS profiles and codec presets replace the `qos` and
+    /// `codec` sections before the individual options are applied.
+    pub fn load(args: &ConfigArgs) -> Result<Self, Error> {
+        let mut config = match &args.config {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| Error(format!("Reading config file {:?} failed: {}", path, e)))?;
+                toml::from_str(&text)
+                    .map_err(|e| Error(format!("Invalid config file {:?}: {}", path, e)))?
+            }
+            None => Config::default(),
+        };
+
+        if let Some(domain_id) = args.domain_id {
+            config.domain_id = domain_id;
+        }
+        if let Some(video_topic) = &args.video_topic {
+            config.video_topic = video_topic.clone();
+        }
+        if args.qos_profile.is_some() {
+            config.qos_profile = args.qos_profile.clone();
+        }
+        if args.codec_preset.is_some() {
+            config.codec_preset = args.codec_preset.clone();
+        }
+
+        if let Some(name) = &config.qos_profile {
+            config.qos = config
+                .qos_profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error(format!("Unknown QoS profile {:?}", name)))?;
+        }
+        if let Some(name) = &config.codec_preset {
+            config.codec = config
+                .codec_presets
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error(format!("Unknown codec preset {:?}", name)))?;
+        }
+
+        if args.framerate.is_some() {
+            config.qos.framerate = args.framerate;
+        }
+        if !args.rooms.is_empty() {
+            config.qos.rooms = args.rooms.clone();
+        }
+        if !args.encoders.is_empty() {
+            config.codec.encoders = args.encoders.clone();
+        }
+        if !args.decoders.is_empty() {
+            config.codec.decoders = args.decoders.clone();
+        }
+        if let Some(bitrate) = args.bitrate {
+            config.codec.bitrate = bitrate;
+        }
+        if let Some(source) = &args.source {
+            config.publisher.source = source.clone();
+        }
+        if let Some(sink) = &args.sink {
+            config.publisher.sink = sink.clone();
+            config.subscriber.sink = sink.clone();
+        }
+
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> Result<String, Error> {
+        toml::to_string_pretty(self).map_err(|e| Error(format!("Serializing config failed: {}", e)))
+    }
+}
@@ -0,0 +1,326 @@
+use crate::{error::Error, pipeline::CaptureControls};
+use dust_dds::{
+    publication::data_writer::DataWriter,
+    subscription::{
+        data_reader::DataReader,
+        data_reader_listener::DataReaderListener,
+        sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+    },
+};
+
+pub const CAMERA_CONTROL_TOPIC_NAME: &str = "CameraControl";
+pub const CAMERA_CONTROL_TYPE_NAME: &str = "CameraControl";
+pub const CAMERA_STATE_TOPIC_NAME: &str = "CameraState";
+pub const CAMERA_STATE_TYPE_NAME: &str = "CameraState";
+
+/// Announced in the stream info of publishers accepting control requests
+pub const CAMERA_CONTROL_CAPABILITY: &str = "camera-control";
+
+/// Largest width and height of the camera picture and of snapshots
+pub const MAX_DIMENSION: u32 = 4096;
+const MAX_FRAMERATE: u32 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// Bitrate in bit/s
+    SetBitrate(u32),
+    SetResolution {
+        width: u32,
+        height: u32,
+    },
+    SetFramerate(u32),
+    /// Stop sending frames
+    Pause,
+    Resume,
+    /// Send black frames instead of the camera picture
+    Mute,
+    Unmute,
+}
+
+impl ControlCommand {
+    /// Command from its name and arguments, as in the samples and on the
+    /// standard input of the subscriber, e.g. `resolution 640 480`
+    pub fn new(name: &str, arguments: &[u32]) -> Result<Self, Error> {
+        let command = match (name, arguments) {
+            ("bitrate", &[bitrate]) if bitrate > 0 => ControlCommand::SetBitrate(bitrate),
+            ("resolution", &[width, height])
+                if (2..=MAX_DIMENSION).contains(&width)
+                    && (2..=MAX_DIMENSION).contains(&height)
+                    && width % 2 == 0
+                    && height % 2 == 0 =>
+            {
+                ControlCommand::SetResolution { width, height }
+            }
+            ("framerate", &[framerate]) if (1..=MAX_FRAMERATE).contains(&framerate) => {
+                ControlCommand::SetFramerate(framerate)
+            }
+            ("pause", &[]) => ControlCommand::Pause,
+            ("resume", &[]) => ControlCommand::Resume,
+            ("mute", &[]) => ControlCommand::Mute,
+            ("unmute", &[]) => ControlCommand::Unmute,
+            _ => {
+                return Err(Error(format!(
+                    "Invalid control command {:?} {:?}, expected `bitrate <bit/s>`, \
+                     `resolution <width> <height>` with even sizes, `framerate <fps>`, \
+                     `pause`, `resume`, `mute` or `unmute`",
+                    name, arguments
+                )))
+            }
+        };
+        Ok(command)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ControlCommand::SetBitrate(_) => "bitrate",
+            ControlCommand::SetResolution { .. } => "resolution",
+            ControlCommand::SetFramerate(_) => "framerate",
+            ControlCommand::Pause => "pause",
+            ControlCommand::Resume => "resume",
+            ControlCommand::Mute => "mute",
+            ControlCommand::Unmute => "unmute",
+        }
+    }
+
+    pub fn arguments(&self) -> Vec<u32> {
+        match *self {
+            ControlCommand::SetBitrate(bitrate) => vec![bitrate],
+            ControlCommand::SetResolution { width, height } => vec![width, height],
+            ControlCommand::SetFramerate(framerate) => vec![framerate],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Request of an operator to change the stream of `user_id`. Every operator
+/// numbers its requests, the publisher acknowledges them in its `CameraState`.
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct CameraControl {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    /// Host name of the requesting subscriber, as it claims it
+    #[dust_dds(key)]
+    pub operator: String,
+    pub sequence: u32,
+    pub command: String,
+    pub arguments: Vec<u32>,
+}
+
+impl CameraControl {
+    pub fn new(user_id: i16, sequence: u32, command: ControlCommand) -> Self {
+        Self {
+            user_id,
+            operator: gethostname::gethostname().to_string_lossy().into_owned(),
+            sequence,
+            command: command.name().to_string(),
+            arguments: command.arguments(),
+        }
+    }
+
+    pub fn command(&self) -> Result<ControlCommand, Error> {
+        ControlCommand::new(&self.command, &self.arguments)
+    }
+}
+
+/// Current settings of a publisher and the outcome of the last request, kept
+/// for subscribers joining later
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct CameraState {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    /// Operator and sequence number of the last request, empty initially
+    pub operator: String,
+    pub sequence: u32,
+    pub accepted: bool,
+    /// Reason a request was rejected
+    pub message: String,
+    pub bitrate: u32,
+    /// 0 while the camera resolution is used
+    pub width: u32,
+    pub height: u32,
+    /// 0 while the camera framerate is used
+    pub framerate: u32,
+    pub paused: bool,
+    pub muted: bool,
+}
+
+impl std::fmt::Display for CameraState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "user_id {}: {} kbit/s, {}x{}, {} fps{}{}",
+            self.user_id,
+            self.bitrate / 1000,
+            self.width,
+            self.height,
+            self.framerate,
+            if self.paused { ", paused" } else { "" },
+            if self.muted { ", muted" } else { "" }
+        )?;
+        if !self.operator.is_empty() {
+            write!(
+                f,
+                " (request {} of {} {})",
+                self.sequence,
+                self.operator,
+                if self.accepted {
+                    "accepted"
+                } else {
+                    "rejected"
+                }
+            )?;
+            if !self.message.is_empty() {
+                write!(f, ": {}", self.message)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies the requests of the authorized operators addressed to `user_id`
+/// to the capture pipeline and acknowledges every request by writing the
+/// resulting state
+pub struct CameraControlListener {
+    controls: CaptureControls,
+    operators: Vec<String>,
+    writer: DataWriter<CameraState>,
+    state: CameraState,
+}
+
+impl CameraControlListener {
+    /// Announces the initial state. `operators` are the host names allowed
+    /// to control the camera. The `operator` of a request is not verified,
+    /// anyone in the domain can claim an allowed host name.
+    pub fn new(
+        controls: CaptureControls,
+        operators: Vec<String>,
+        writer: DataWriter<CameraState>,
+        user_id: i16,
+        bitrate: u32,
+    ) -> Self {
+        let listener = Self {
+            controls,
+            operators,
+            writer,
+            state: CameraState {
+                user_id,
+                operator: String::new(),
+                sequence: 0,
+                accepted: true,
+                message: String::new(),
+                bitrate,
+                width: 0,
+                height: 0,
+                framerate: 0,
+                paused: false,
+                muted: false,
+            },
+        };
+        listener.announce();
+        listener
+    }
+
+    fn announce(&self) {
+        if let Err(e) = self.writer.write(&self.state, None) {
+            println!("Writing camera state failed: {:?}", e);
+        }
+    }
+
+    fn apply(&mut self, command: ControlCommand) {
+        let state = &mut self.state;
+        match command {
+            ControlCommand::SetBitrate(bitrate) => {
+                self.controls.set_bitrate(bitrate);
+                state.bitrate = bitrate;
+            }
+            ControlCommand::SetResolution { width, height } => {
+                state.width = width;
+                state.height = height;
+            }
+            ControlCommand::SetFramerate(framerate) => state.framerate = framerate,
+            ControlCommand::Pause | ControlCommand::Resume => {
+                state.paused = command == ControlCommand::Pause;
+                self.controls.set_paused(state.paused);
+            }
+            ControlCommand::Mute | ControlCommand::Unmute => {
+                state.muted = command == ControlCommand::Mute;
+                self.controls.set_muted(state.muted);
+            }
+        }
+        if let ControlCommand::SetResolution { .. } | ControlCommand::SetFramerate(_) = command {
+            let resolution = (state.width != 0).then_some((state.width, state.height));
+            let framerate = (state.framerate != 0).then_some(state.framerate);
+            self.controls.set_format(resolution, framerate);
+        }
+    }
+
+    fn handle(&mut self, request: &CameraControl) {
+        self.state.operator = request.operator.clone();
+        self.state.sequence = request.sequence;
+        let result = if self.operators.contains(&request.operator) {
+            request.command()
+        } else {
+            Err(Error(format!(
+                "{} is not authorized to control the camera",
+                request.operator
+            )))
+        };
+        match result {
+            Ok(command) => {
+                self.apply(command);
+                self.state.accepted = true;
+                self.state.message.clear();
+            }
+            Err(Error(message)) => {
+                self.state.accepted = false;
+                self.state.message = message;
+            }
+        }
+        println!("Camera control: {}", self.state);
+        self.announce();
+    }
+}
+
+impl<'a> DataReaderListener<'a> for CameraControlListener {
+    type Foo = CameraControl;
+
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
+            for sample in samples {
+                if let Ok(request) = sample.data() {
+                    if request.user_id == self.state.user_id {
+                        self.handle(&request);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Prints the state of the cameras, including the acknowledgements of the
+/// control requests
+pub struct CameraStateListener;
+
+impl<'a> DataReaderListener<'a> for CameraStateListener {
+    type Foo = CameraState;
+
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
+            for sample in samples {
+                if let Ok(state) = sample.data() {
+                    println!("Camera state: {}", state);
+                }
+            }
+        }
+    }
+}
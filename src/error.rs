@@ -0,0 +1,39 @@
+use dust_dds::infrastructure::error::DdsError;
+
+#[derive(Debug)]
+pub struct Error(pub String);
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+impl From<gstreamer::glib::Error> for Error {
+    fn from(value: gstreamer::glib::Error) -> Self {
+        Self(format!("GStreamer error: {}", value))
+    }
+}
+impl From<gstreamer::glib::BoolError> for Error {
+    fn from(value: gstreamer::glib::BoolError) -> Self {
+        Self(format!("GStreamer error: {}", value))
+    }
+}
+impl From<gstreamer::PadLinkError> for Error {
+    fn from(value: gstreamer::PadLinkError) -> Self {
+        Self(format!("GStreamer pad link error: {:?}", value))
+    }
+}
+impl From<gstreamer::StateChangeError> for Error {
+    fn from(value: gstreamer::StateChangeError) -> Self {
+        Self(format!("GStreamer state change error: {}", value))
+    }
+}
+impl From<&gstreamer::message::Error> for Error {
+    fn from(value: &gstreamer::message::Error) -> Self {
+        Self(format!("GStreamer state change error: {:?}", value))
+    }
+}
+impl From<DdsError> for Error {
+    fn from(value: DdsError) -> Self {
+        Self(format!("DDS error: {:?}", value))
+    }
+}
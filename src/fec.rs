@@ -0,0 +1,452 @@
+use crate::h264;
+use dust_dds::{
+    publication::data_writer::DataWriter,
+    subscription::{
+        data_reader::DataReader,
+        data_reader_listener::DataReaderListener,
+        sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+    },
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+pub const PARITY_TYPE_NAME: &str = "VideoParity";
+
+/// Largest number of frames protected by one parity sample
+pub const MAX_GROUP_SIZE: u32 = 32;
+
+/// Name of the topic carrying the parity of the streams of the `video_topic`
+pub fn parity_topic_name(video_topic: &str) -> String {
+    format!("{}Parity", video_topic)
+}
+
+/// XOR parity over a group of consecutive frames of a stream. Any single
+/// frame lost out of the group can be recovered from the other frames and
+/// the parity.
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct VideoParity {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    pub first_frame_num: i32,
+    /// Length of every frame of the group, the parity is as long as the longest
+    pub frame_lengths: Vec<u32>,
+    /// Temporal layer of every frame of the group
+    pub temporal_layers: Vec<u8>,
+    pub parity: Vec<u8>,
+}
+
+impl VideoParity {
+    fn frame_nums(&self) -> impl Iterator<Item = i32> + '_ {
+        (0..self.frame_lengths.len() as i32).map(|i| self.first_frame_num.wrapping_add(i))
+    }
+
+    /// Number of frames protected by the parity
+    pub fn group_size(&self) -> usize {
+        self.frame_lengths.len()
+    }
+
+    fn last_frame_num(&self) -> i32 {
+        self.first_frame_num
+            .wrapping_add(self.frame_lengths.len() as i32 - 1)
+    }
+}
+
+fn xor_into(parity: &mut Vec<u8>, frame: &[u8]) {
+    if parity.len() < frame.len() {
+        parity.resize(frame.len(), 0);
+    }
+    for (p, f) in parity.iter_mut().zip(frame) {
+        *p ^= f;
+    }
+}
+
+/// Parity of the frames of the current group
+struct ParityGroup {
+    group_size: usize,
+    parity: VideoParity,
+}
+
+impl ParityGroup {
+    fn new(user_id: i16, group_size: u32) -> Self {
+        Self {
+            group_size: group_size.clamp(1, MAX_GROUP_SIZE) as usize,
+            parity: VideoParity {
+                user_id,
+                first_frame_num: 0,
+                frame_lengths: Vec::new(),
+                temporal_layers: Vec::new(),
+                parity: Vec::new(),
+            },
+        }
+    }
+
+    /// Adds a frame to the group and returns the parity once the group is
+    /// complete. The next frame starts a new group. A group only covers
+    /// consecutive frames, so a gap left by a dropped frame closes the group
+    /// early and its parity is returned.
+    fn add(&mut self, frame_num: i32, temporal_layer: u8, frame: &[u8]) -> Option<VideoParity> {
+        let is_consecutive = self.parity.frame_lengths.is_empty()
+            || frame_num == self.parity.last_frame_num().wrapping_add(1);
+        let closed = if is_consecutive {
+            None
+        } else {
+            Some(self.take())
+        };
+
+        if self.parity.frame_lengths.is_empty() {
+            self.parity.first_frame_num = frame_num;
+        }
+        self.parity.frame_lengths.push(frame.len() as u32);
+        self.parity.temporal_layers.push(temporal_layer);
+        xor_into(&mut self.parity.parity, frame);
+
+        if self.parity.frame_lengths.len() < self.group_size {
+            return closed;
+        }
+        Some(self.take())
+    }
+
+    fn take(&mut self) -> VideoParity {
+        let parity = self.parity.clone();
+        self.parity.frame_lengths.clear();
+        self.parity.temporal_layers.clear();
+        self.parity.parity.clear();
+        parity
+    }
+}
+
+/// Writes a parity sample after every `group_size` frames, or earlier when
+/// the send queue dropped a frame. The overhead is about one frame in
+/// `group_size`.
+pub struct ParityEncoder {
+    writer: DataWriter<VideoParity>,
+    group: ParityGroup,
+}
+
+impl ParityEncoder {
+    pub fn new(writer: DataWriter<VideoParity>, user_id: i16, group_size: u32) -> Self {
+        Self {
+            writer,
+            group: ParityGroup::new(user_id, group_size),
+        }
+    }
+
+    pub fn add(&mut self, frame_num: i32, temporal_layer: u8, frame: &[u8]) {
+        if let Some(parity) = self.group.add(frame_num, temporal_layer, frame) {
+            if let Err(e) = self.writer.write(&parity, None) {
+                println!("Writing parity failed: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Result of checking the parity of a complete group
+pub enum Recovery {
+    /// The lost frame, rebuilt from the parity
+    Recovered {
+        frame_num: i32,
+        temporal_layer: u8,
+        frame: Vec<u8>,
+    },
+    /// More than one frame of the group was lost
+    Unrecoverable { lost_frames: u64 },
+}
+
+/// Recovers lost frames of one stream from the parity of their group. A group
+/// is checked once a frame after it was received, so a frame is recovered
+/// about one frame interval after its group ended.
+///
+/// A keyframe behind the newest frame, or any frame far behind it, is
+/// numbered by a restarted or another publisher. The decoder then starts
+/// over, as the frames and parity kept are unrelated to the new frames.
+#[derive(Default)]
+pub struct FecDecoder {
+    frames: BTreeMap<i32, Vec<u8>>,
+    parities: Vec<VideoParity>,
+    newest_frame_num: Option<i32>,
+}
+
+impl FecDecoder {
+    pub fn receive(&mut self, frame_num: i32, frame: &[u8]) {
+        if let Some(newest) = self.newest_frame_num {
+            let behind = newest.wrapping_sub(frame_num);
+            if behind > 2 * MAX_GROUP_SIZE as i32 || (behind > 0 && h264::is_keyframe(frame)) {
+                *self = Self::default();
+            }
+        }
+        match self.newest_frame_num {
+            Some(newest) if newest.wrapping_sub(frame_num) >= 0 => (),
+            _ => self.newest_frame_num = Some(frame_num),
+        }
+        self.frames.insert(frame_num, frame.to_vec());
+        // Keep the frames of the groups that may still be checked
+        let oldest = frame_num.wrapping_sub(2 * MAX_GROUP_SIZE as i32);
+        self.frames.retain(|&n, _| n.wrapping_sub(oldest) >= 0);
+    }
+
+    pub fn add_parity(&mut self, parity: VideoParity) {
+        self.parities.push(parity);
+    }
+
+    /// Checks the parity of all groups ended before `frame_num`
+    pub fn recover(&mut self, frame_num: i32) -> Vec<Recovery> {
+        let (complete, pending): (Vec<_>, Vec<_>) = self
+            .parities
+            .drain(..)
+            .partition(|parity| parity.last_frame_num().wrapping_sub(frame_num) < 0);
+        self.parities = pending;
+
+        let mut recoveries = Vec::new();
+        for parity in complete {
+            let missing: Vec<_> = parity
+                .frame_nums()
+                .enumerate()
+                .filter(|(_, n)| !self.frames.contains_key(n))
+                .collect();
+            match missing.as_slice() {
+                [] => (),
+                [(index, missing_frame_num)] => {
+                    let mut frame = parity.parity.clone();
+                    for n in parity.frame_nums().filter(|n| n != missing_frame_num) {
+                        xor_into(&mut frame, &self.frames[&n]);
+                    }
+                    frame.truncate(parity.frame_lengths[*index] as usize);
+                    self.frames.insert(*missing_frame_num, frame.clone());
+                    recoveries.push(Recovery::Recovered {
+                        frame_num: *missing_frame_num,
+                        temporal_layer: parity
+                            .temporal_layers
+                            .get(*index)
+                            .copied()
+                            .unwrap_or_default(),
+                        frame,
+                    });
+                }
+                _ => recoveries.push(Recovery::Unrecoverable {
+                    lost_frames: missing.len() as u64,
+                }),
+            }
+        }
+        recoveries
+    }
+}
+
+/// Received parity samples of every `user_id` not yet handed to a decoder,
+/// shared between the parity listener and the video listener
+#[derive(Clone, Default)]
+pub struct ParityCache(Arc<Mutex<HashMap<i16, Vec<VideoParity>>>>);
+
+impl ParityCache {
+    pub fn take(&self, user_id: i16) -> Vec<VideoParity> {
+        self.0
+            .lock()
+            .expect("parity lock not poisoned")
+            .remove(&user_id)
+            .unwrap_or_default()
+    }
+
+    fn insert(&self, parity: VideoParity) {
+        self.0
+            .lock()
+            .expect("parity lock not poisoned")
+            .entry(parity.user_id)
+            .or_default()
+            .push(parity);
+    }
+}
+
+/// Stores all received parity samples in the cache
+pub struct ParityListener {
+    pub cache: ParityCache,
+}
+
+impl<'a> DataReaderListener<'a> for ParityListener {
+    type Foo = VideoParity;
+
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
+            for sample in samples {
+                if let Ok(parity) = sample.data() {
+                    self.cache.insert(parity);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames() -> Vec<Vec<u8>> {
+        vec![
+            vec![0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x84],
+            vec![0x00, 0x00, 0x00, 0x01, 0x41, 0x9a],
+            vec![0x00, 0x00, 0x00, 0x01, 0x41, 0x9b, 0x02, 0x10, 0x7f],
+            vec![0x00, 0x00, 0x00, 0x01, 0x01],
+        ]
+    }
+
+    /// Decoder that received all frames but `lost` and the parity of the
+    /// frames starting at `first_frame_num`
+    fn decoder(first_frame_num: i32, lost: &[i32]) -> FecDecoder {
+        let mut group = ParityGroup::new(1, frames().len() as u32);
+        let mut decoder = FecDecoder::default();
+        for (i, frame) in frames().iter().enumerate() {
+            let frame_num = first_frame_num.wrapping_add(i as i32);
+            if let Some(parity) = group.add(frame_num, i as u8 % 2, frame) {
+                decoder.add_parity(parity);
+            }
+            if !lost.contains(&frame_num) {
+                decoder.receive(frame_num, frame);
+            }
+        }
+        decoder
+    }
+
+    #[test]
+    fn group_parity_is_written_once_complete() {
+        let mut group = ParityGroup::new(1, 2);
+        assert!(group.add(10, 0, &[0x0f, 0xf0]).is_none());
+        let parity = group.add(11, 1, &[0xff]).unwrap();
+        assert_eq!(parity.first_frame_num, 10);
+        assert_eq!(parity.frame_lengths, vec![2, 1]);
+        assert_eq!(parity.temporal_layers, vec![0, 1]);
+        assert_eq!(parity.parity, vec![0xf0, 0xf0]);
+
+        // The next frame starts a new group
+        assert!(group.add(12, 0, &[0x01]).is_none());
+        let parity = group.add(13, 1, &[0x02]).unwrap();
+        assert_eq!(parity.first_frame_num, 12);
+        assert_eq!(parity.parity, vec![0x03]);
+    }
+
+    #[test]
+    fn restarted_publishers_start_the_decoder_over() {
+        // Group 0 to 3 of the old publisher is missing frame 2
+        let mut decoder = decoder(0, &[2]);
+
+        // The restarted publisher starts again with keyframe 0 and loses
+        // frame 2 of its own group 0 to 3
+        let old = frames();
+        let new = [&old[0], &old[2], &old[1], &old[3]];
+        let mut group = ParityGroup::new(1, new.len() as u32);
+        for (frame_num, frame) in new.into_iter().enumerate() {
+            let frame_num = frame_num as i32;
+            if frame_num != 2 {
+                decoder.receive(frame_num, frame);
+            }
+            if let Some(parity) = group.add(frame_num, 0, frame) {
+                decoder.add_parity(parity);
+            }
+        }
+        match decoder.recover(4).as_slice() {
+            [Recovery::Recovered {
+                frame_num, frame, ..
+            }] => {
+                assert_eq!(*frame_num, 2);
+                assert_eq!(frame, new[2]);
+            }
+            _ => panic!("expected only the frame of the new group"),
+        }
+    }
+
+    #[test]
+    fn dropped_frames_close_the_group() {
+        let mut group = ParityGroup::new(1, 4);
+        let mut decoder = FecDecoder::default();
+        let frames = frames();
+        // Frame 2 was dropped before it was written, frame 1 is lost
+        for (frame_num, frame) in [(0, &frames[0]), (1, &frames[1]), (3, &frames[2])] {
+            if let Some(parity) = group.add(frame_num, 0, frame) {
+                assert_eq!(parity.first_frame_num, 0);
+                assert_eq!(parity.group_size(), 2);
+                decoder.add_parity(parity);
+            }
+            if frame_num != 1 {
+                decoder.receive(frame_num, frame);
+            }
+        }
+        match decoder.recover(3).as_slice() {
+            [Recovery::Recovered {
+                frame_num, frame, ..
+            }] => {
+                assert_eq!(*frame_num, 1);
+                assert_eq!(*frame, frames[1]);
+            }
+            _ => panic!("frame 1 was not recovered"),
+        }
+
+        // The next group starts at the frame after the gap
+        assert!(group.add(4, 0, &frames[3]).is_none());
+        assert!(group.add(5, 0, &frames[0]).is_none());
+        let parity = group.add(6, 0, &frames[1]).unwrap();
+        assert_eq!(parity.first_frame_num, 3);
+        assert_eq!(parity.group_size(), 4);
+    }
+
+    #[test]
+    fn recovers_any_single_lost_frame() {
+        for (i, frame) in frames().into_iter().enumerate() {
+            let lost = 20 + i as i32;
+            let mut decoder = decoder(20, &[lost]);
+            match decoder.recover(24).as_slice() {
+                [Recovery::Recovered {
+                    frame_num,
+                    temporal_layer,
+                    frame: recovered,
+                }] => {
+                    assert_eq!(*frame_num, lost);
+                    assert_eq!(*temporal_layer, i as u8 % 2);
+                    assert_eq!(*recovered, frame);
+                }
+                _ => panic!("frame {} was not recovered", lost),
+            }
+        }
+    }
+
+    #[test]
+    fn recovers_across_frame_num_wrap_around() {
+        let mut decoder = decoder(i32::MAX - 1, &[i32::MIN]);
+        match decoder.recover(i32::MIN + 2).as_slice() {
+            [Recovery::Recovered {
+                frame_num, frame, ..
+            }] => {
+                assert_eq!(*frame_num, i32::MIN);
+                assert_eq!(*frame, frames()[2]);
+            }
+            _ => panic!("frame was not recovered"),
+        }
+    }
+
+    #[test]
+    fn reports_groups_with_several_lost_frames() {
+        let mut decoder = decoder(0, &[1, 3]);
+        match decoder.recover(4).as_slice() {
+            [Recovery::Unrecoverable { lost_frames }] => assert_eq!(*lost_frames, 2),
+            _ => panic!("expected an unrecoverable group"),
+        }
+    }
+
+    #[test]
+    fn complete_groups_need_no_recovery() {
+        let mut decoder = decoder(0, &[]);
+        assert!(decoder.recover(4).is_empty());
+    }
+
+    #[test]
+    fn groups_are_checked_after_their_last_frame() {
+        let mut decoder = decoder(0, &[2]);
+        assert!(decoder.recover(3).is_empty());
+        assert_eq!(decoder.recover(4).len(), 1);
+        // A group is only checked once
+        assert!(decoder.recover(5).is_empty());
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{h264, video::Video};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Reader-side filter deciding which received frames are decoded.
+///
+/// Dropping a frame breaks all following delta frames of the stream, so once a
+/// frame of a `user_id` was dropped, or a gap in `frame_num` is detected, the
+/// stream is only resumed at the next keyframe. For the same reason the
+/// minimum separation is not implemented with the TIME_BASED_FILTER QoS: the
+/// middleware would pick the samples regardless of their frame type. Frames of
+/// temporal layers above `max_temporal_layer` are the exception, the layers
+/// below do not depend on them.
+#[derive(Clone)]
+pub struct VideoFilter {
+    user_ids: Vec<i16>,
+    keyframes_only: bool,
+    minimum_separation: Option<Duration>,
+    max_temporal_layer: Option<u8>,
+    streams: HashMap<i16, StreamState>,
+}
+
+#[derive(Clone)]
+struct StreamState {
+    last_frame_num: i32,
+    last_accepted: Option<Instant>,
+    waiting_for_keyframe: bool,
+}
+
+impl VideoFilter {
+    /// An empty `user_ids` list accepts all streams
+    pub fn new(
+        user_ids: Vec<i16>,
+        keyframes_only: bool,
+        minimum_separation: Option<Duration>,
+        max_temporal_layer: Option<u8>,
+    ) -> Self {
+        Self {
+            user_ids,
+            keyframes_only,
+            minimum_separation,
+            max_temporal_layer,
+            streams: HashMap::new(),
+        }
+    }
+
+    pub fn accepts(&mut self, video: &Video) -> bool {
+        if !self.user_ids.is_empty() && !self.user_ids.contains(&video.user_id) {
+            return false;
+        }
+        let is_keyframe = h264::is_keyframe(video.frame);
+        if self.keyframes_only && !is_keyframe {
+            return false;
+        }
+
+        let now = Instant::now();
+        let stream = self.streams.entry(video.user_id).or_insert(StreamState {
+            last_frame_num: video.frame_num.wrapping_sub(1),
+            last_accepted: None,
+            waiting_for_keyframe: true,
+        });
+
+        let is_consecutive = video.frame_num == stream.last_frame_num.wrapping_add(1);
+        stream.last_frame_num = video.frame_num;
+        if self
+            .max_temporal_layer
+            .is_some_and(|max_temporal_layer| video.temporal_layer > max_temporal_layer)
+        {
+            return false;
+        }
+        let is_separated = match (self.minimum_separation, stream.last_accepted) {
+            (Some(separation), Some(last_accepted)) => now - last_accepted >= separation,
+            _ => true,
+        };
+
+        if !is_separated || (!is_keyframe && !is_consecutive) {
+            stream.waiting_for_keyframe = true;
+        } else if is_keyframe {
+            stream.waiting_for_keyframe = false;
+        }
+
+        let accepted = is_separated && !stream.waiting_for_keyframe;
+        if accepted {
+            stream.last_accepted = Some(now);
+        }
+        accepted
+    }
+}
@@ -0,0 +1,338 @@
+use crate::h264;
+use dust_dds::publication::data_writer::DataWriter;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+pub const FRAGMENT_TYPE_NAME: &str = "VideoFragment";
+
+/// Fragments with a larger count are rejected instead of allocating for them,
+/// so frames are not written in more fragments
+pub const MAX_FRAGMENT_COUNT: usize = 4096;
+
+/// Name of the topic carrying the fragmented frames of the `video_topic`
+pub fn fragment_topic_name(video_topic: &str) -> String {
+    format!("{}Fragment", video_topic)
+}
+
+/// Part of a frame. The fragments of a frame are consecutive pieces of it,
+/// cut on NAL unit boundaries where possible.
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct VideoFragment {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    pub frame_num: i32,
+    pub temporal_layer: u8,
+    pub fragment_index: i32,
+    pub fragment_count: i32,
+    pub data: Vec<u8>,
+}
+
+/// Splits the frame into fragments of at most `max_size` bytes. A fragment
+/// ends at the last NAL unit boundary that fits, NAL units larger than
+/// `max_size` are cut into several fragments.
+pub fn fragment_frame(
+    user_id: i16,
+    frame_num: i32,
+    temporal_layer: u8,
+    frame: &[u8],
+    max_size: usize,
+) -> Vec<VideoFragment> {
+    let max_size = max_size.max(1);
+    let mut boundaries = h264::nal_unit_boundaries(frame);
+    boundaries.push(frame.len());
+
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    while start < frame.len() {
+        let limit = start + max_size;
+        let end = boundaries
+            .iter()
+            .copied()
+            .rev()
+            .find(|&boundary| boundary > start && boundary <= limit)
+            .unwrap_or_else(|| limit.min(frame.len()));
+        pieces.push(&frame[start..end]);
+        start = end;
+    }
+    if pieces.is_empty() {
+        pieces.push(frame);
+    }
+
+    let fragment_count = pieces.len() as i32;
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| VideoFragment {
+            user_id,
+            frame_num,
+            temporal_layer,
+            fragment_index: index as i32,
+            fragment_count,
+            data: data.to_vec(),
+        })
+        .collect()
+}
+
+/// Writes the frames as fragments of at most `max_size` bytes
+pub struct Fragmenter {
+    pub writer: DataWriter<VideoFragment>,
+    pub max_size: usize,
+}
+
+impl Fragmenter {
+    /// Calls `pace` with the size of every fragment before writing it. Returns
+    /// whether all fragments were written.
+    pub fn write(
+        &self,
+        user_id: i16,
+        frame_num: i32,
+        temporal_layer: u8,
+        frame: &[u8],
+        mut pace: impl FnMut(usize),
+    ) -> bool {
+        let fragments = fragment_frame(user_id, frame_num, temporal_layer, frame, self.max_size);
+        if fragments.len() > MAX_FRAGMENT_COUNT {
+            println!(
+                "Dropped frame {} of {} bytes, more than {} fragments of {} bytes",
+                frame_num,
+                frame.len(),
+                MAX_FRAGMENT_COUNT,
+                self.max_size
+            );
+            return false;
+        }
+        for fragment in fragments {
+            pace(fragment.data.len());
+            if let Err(e) = self.writer.write(&fragment, None) {
+                println!(
+                    "Writing fragment {} of frame {} failed: {:?}",
+                    fragment.fragment_index, frame_num, e
+                );
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Frame reassembled from all its fragments
+pub struct ReassembledFrame {
+    pub user_id: i16,
+    pub frame_num: i32,
+    pub temporal_layer: u8,
+    pub frame: Vec<u8>,
+}
+
+/// Frame given up because not all its fragments arrived in time
+pub struct IncompleteFrame {
+    pub user_id: i16,
+    pub frame_num: i32,
+    pub missing_fragments: usize,
+    pub fragment_count: usize,
+}
+
+struct PartialFrame {
+    first_received: Instant,
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+/// Reassembles the fragmented frames of all streams. Frames still incomplete
+/// `timeout` after their first fragment arrived are given up by `expire`,
+/// which is called periodically as no further fragment may arrive.
+pub struct Reassembler {
+    timeout: Duration,
+    frames: HashMap<(i16, i32), PartialFrame>,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            frames: HashMap::new(),
+        }
+    }
+
+    /// Returns the frame once its last missing fragment arrived
+    pub fn insert(&mut self, fragment: VideoFragment, now: Instant) -> Option<ReassembledFrame> {
+        let fragment_count = usize::try_from(fragment.fragment_count).ok()?;
+        let index = usize::try_from(fragment.fragment_index).ok()?;
+        if index >= fragment_count || fragment_count > MAX_FRAGMENT_COUNT {
+            return None;
+        }
+
+        let key = (fragment.user_id, fragment.frame_num);
+        let partial = self.frames.entry(key).or_insert_with(|| PartialFrame {
+            first_received: now,
+            fragments: vec![None; fragment_count],
+            missing: fragment_count,
+        });
+        // Fragments of the same frame_num cut differently, e.g. by a
+        // restarted publisher, would not fit the fragments received so far
+        if partial.fragments.len() != fragment_count {
+            return None;
+        }
+        let slot = partial.fragments.get_mut(index)?;
+        if slot.is_none() {
+            *slot = Some(fragment.data);
+            partial.missing -= 1;
+        }
+        if partial.missing > 0 {
+            return None;
+        }
+
+        let partial = self.frames.remove(&key).expect("Partial frame exists");
+        Some(ReassembledFrame {
+            user_id: fragment.user_id,
+            frame_num: fragment.frame_num,
+            temporal_layer: fragment.temporal_layer,
+            frame: partial.fragments.into_iter().flatten().flatten().collect(),
+        })
+    }
+
+    /// Gives up the frames that timed out
+    pub fn expire(&mut self, now: Instant) -> Vec<IncompleteFrame> {
+        let timeout = self.timeout;
+        let expired: Vec<_> = self
+            .frames
+            .iter()
+            .filter(|(_, partial)| now - partial.first_received > timeout)
+            .map(|(&key, _)| key)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|(user_id, frame_num)| {
+                let partial = self.frames.remove(&(user_id, frame_num))?;
+                Some(IncompleteFrame {
+                    user_id,
+                    frame_num,
+                    missing_fragments: partial.missing,
+                    fragment_count: partial.fragments.len(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// SPS, PPS and IDR slice, starting at 0, 8 and 14
+    fn frame() -> Vec<u8> {
+        let mut frame = vec![0, 0, 0, 1, 0x67, 1, 2, 3];
+        frame.extend_from_slice(&[0, 0, 0, 1, 0x68, 4]);
+        frame.extend_from_slice(&[0, 0, 1, 0x65, 5, 6, 7, 8, 9, 10, 11, 12]);
+        frame
+    }
+
+    fn lengths(fragments: &[VideoFragment]) -> Vec<usize> {
+        fragments
+            .iter()
+            .map(|fragment| fragment.data.len())
+            .collect()
+    }
+
+    #[test]
+    fn fragments_end_on_nal_unit_boundaries() {
+        let fragments = fragment_frame(1, 7, 2, &frame(), 14);
+        assert_eq!(lengths(&fragments), vec![14, 12]);
+        for (index, fragment) in fragments.iter().enumerate() {
+            assert_eq!(fragment.fragment_index, index as i32);
+            assert_eq!(fragment.fragment_count, 2);
+            assert_eq!((fragment.frame_num, fragment.temporal_layer), (7, 2));
+        }
+    }
+
+    #[test]
+    fn nal_units_larger_than_the_fragments_are_cut() {
+        let fragments = fragment_frame(1, 7, 0, &frame(), 10);
+        assert_eq!(lengths(&fragments), vec![8, 6, 10, 2]);
+    }
+
+    #[test]
+    fn reassembles_fragments_in_any_order() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(TIMEOUT);
+        let mut fragments = fragment_frame(1, 7, 2, &frame(), 10);
+        let last = fragments.remove(0);
+        for fragment in fragments.into_iter().rev() {
+            assert!(reassembler.insert(fragment.clone(), now).is_none());
+            // Duplicates are ignored
+            assert!(reassembler.insert(fragment, now).is_none());
+        }
+        let reassembled = reassembler.insert(last, now).unwrap();
+        assert_eq!((reassembled.user_id, reassembled.frame_num), (1, 7));
+        assert_eq!(reassembled.temporal_layer, 2);
+        assert_eq!(reassembled.frame, frame());
+    }
+
+    #[test]
+    fn frames_of_different_streams_are_kept_apart() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(TIMEOUT);
+        let first = fragment_frame(1, 7, 0, &frame(), 14);
+        let second = fragment_frame(2, 7, 0, &frame()[..14], 8);
+        assert!(reassembler.insert(first[0].clone(), now).is_none());
+        assert!(reassembler.insert(second[0].clone(), now).is_none());
+        let reassembled = reassembler.insert(second[1].clone(), now).unwrap();
+        assert_eq!(reassembled.user_id, 2);
+        assert_eq!(reassembled.frame, &frame()[..14]);
+        let reassembled = reassembler.insert(first[1].clone(), now).unwrap();
+        assert_eq!(reassembled.user_id, 1);
+        assert_eq!(reassembled.frame, frame());
+    }
+
+    #[test]
+    fn gives_up_frames_missing_fragments_after_the_timeout() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(TIMEOUT);
+        let fragments = fragment_frame(1, 7, 0, &frame(), 10);
+        assert!(reassembler.insert(fragments[1].clone(), now).is_none());
+        assert!(reassembler.insert(fragments[3].clone(), now).is_none());
+
+        assert!(reassembler.expire(now + TIMEOUT).is_empty());
+        let expired = reassembler.expire(now + TIMEOUT + Duration::from_millis(1));
+        match expired.as_slice() {
+            [incomplete] => {
+                assert_eq!((incomplete.user_id, incomplete.frame_num), (1, 7));
+                assert_eq!(incomplete.missing_fragments, 2);
+                assert_eq!(incomplete.fragment_count, 4);
+            }
+            _ => panic!("expected one incomplete frame"),
+        }
+        assert!(reassembler.expire(now + 2 * TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_fragments() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(TIMEOUT);
+        let mut fragment = fragment_frame(1, 7, 0, &frame(), 100).remove(0);
+        fragment.fragment_index = 1;
+        assert!(reassembler.insert(fragment.clone(), now).is_none());
+        fragment.fragment_index = 0;
+        fragment.fragment_count = MAX_FRAGMENT_COUNT as i32 + 1;
+        assert!(reassembler.insert(fragment, now).is_none());
+        assert!(reassembler.expire(now + 2 * TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn rejects_fragments_of_a_different_count() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(TIMEOUT);
+        let fragments = fragment_frame(1, 7, 0, &frame(), 10);
+        let other = fragment_frame(1, 7, 0, &frame(), 14);
+        assert!(reassembler.insert(fragments[0].clone(), now).is_none());
+        assert!(reassembler.insert(other[1].clone(), now).is_none());
+        for fragment in &fragments[1..3] {
+            assert!(reassembler.insert(fragment.clone(), now).is_none());
+        }
+        let reassembled = reassembler.insert(fragments[3].clone(), now).unwrap();
+        assert_eq!(reassembled.frame, frame());
+    }
+}
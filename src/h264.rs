@@ -0,0 +1,429 @@
+pub const NAL_UNIT_TYPE_NON_IDR: u8 = 1;
+pub const NAL_UNIT_TYPE_IDR: u8 = 5;
+pub const NAL_UNIT_TYPE_SPS: u8 = 7;
+pub const NAL_UNIT_TYPE_PPS: u8 = 8;
+
+/// Iterator over the NAL units of an H.264 Annex B byte stream. The returned
+/// slices start with the NAL unit header and do not include the start code.
+pub struct NalUnits<'a> {
+    data: &'a [u8],
+}
+
+pub fn nal_units(data: &[u8]) -> NalUnits<'_> {
+    let start = find_start_code(data).map_or(data.len(), |(_, end)| end);
+    NalUnits {
+        data: &data[start..],
+    }
+}
+
+impl<'a> Iterator for NalUnits<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        match find_start_code(self.data) {
+            Some((begin, end)) => {
+                let nal_unit = &self.data[..begin];
+                self.data = &self.data[end..];
+                Some(nal_unit)
+            }
+            None => {
+                let nal_unit = self.data;
+                self.data = &[];
+                Some(nal_unit)
+            }
+        }
+    }
+}
+
+/// Returns the begin and end position of the first 3 or 4 byte start code
+fn find_start_code(data: &[u8]) -> Option<(usize, usize)> {
+    let position = data.windows(3).position(|w| w == [0, 0, 1])?;
+    if position > 0 && data[position - 1] == 0 {
+        Some((position - 1, position + 3))
+    } else {
+        Some((position, position + 3))
+    }
+}
+
+/// Positions of the start codes of all NAL units, i.e. the positions at which
+/// the byte stream can be split without cutting a NAL unit
+pub fn nal_unit_boundaries(data: &[u8]) -> Vec<usize> {
+    let mut boundaries = Vec::new();
+    let mut offset = 0;
+    while let Some((begin, end)) = find_start_code(&data[offset..]) {
+        boundaries.push(offset + begin);
+        offset += end;
+    }
+    boundaries
+}
+
+pub fn nal_unit_type(nal_unit: &[u8]) -> Option<u8> {
+    nal_unit.first().map(|header| header & 0x1f)
+}
+
+/// A keyframe contains an IDR slice and can be decoded without any previous frame
+pub fn is_keyframe(frame: &[u8]) -> bool {
+    nal_units(frame).any(|nal_unit| nal_unit_type(nal_unit) == Some(NAL_UNIT_TYPE_IDR))
+}
+
+/// Later frames may be predicted from a reference frame. Frames whose slices
+/// all have a `nal_ref_idc` of 0 can be dropped without affecting the others.
+pub fn is_reference(frame: &[u8]) -> bool {
+    let mut slices = nal_units(frame)
+        .filter(|nal_unit| {
+            matches!(
+                nal_unit_type(nal_unit),
+                Some(NAL_UNIT_TYPE_NON_IDR | NAL_UNIT_TYPE_IDR)
+            )
+        })
+        .peekable();
+    slices.peek().is_none() || slices.any(|slice| slice[0] & 0x60 != 0)
+}
+
+/// Temporal layer of a frame of a stream encoded with `layer_count` temporal
+/// layers, 0 being the base layer. The layers are told apart by the
+/// `nal_ref_idc` of the slices, as x264 marks them: P and I frames are the
+/// base layer, referenced B frames the middle layer and unreferenced B frames
+/// the top layer. Every layer is only predicted from the layers below it. The
+/// publisher, which configured the encoder, marks every frame with its layer.
+pub fn temporal_layer(frame: &[u8], layer_count: u8) -> u8 {
+    let nal_ref_idc = nal_units(frame)
+        .filter(|nal_unit| {
+            matches!(
+                nal_unit_type(nal_unit),
+                Some(NAL_UNIT_TYPE_NON_IDR | NAL_UNIT_TYPE_IDR)
+            )
+        })
+        .map(|slice| (slice[0] & 0x60) >> 5)
+        .max();
+    match nal_ref_idc {
+        Some(0) => layer_count.saturating_sub(1),
+        Some(1) if layer_count > 2 => 1,
+        _ => 0,
+    }
+}
+
+/// Finds the sequence parameter set of a frame. Encoders repeat it in front
+/// of every keyframe.
+pub fn find_sps(frame: &[u8]) -> Option<SequenceParameterSet> {
+    nal_units(frame)
+        .find(|nal_unit| nal_unit_type(nal_unit) == Some(NAL_UNIT_TYPE_SPS))
+        .and_then(SequenceParameterSet::parse)
+}
+
+/// Latest parameter sets of a stream, so that a keyframe can be decoded on its
+/// own even if the encoder did not repeat them in front of it
+#[derive(Debug, Clone, Default)]
+pub struct ParameterSets {
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+}
+
+impl ParameterSets {
+    /// Remembers the parameter sets contained in the frame
+    pub fn update(&mut self, frame: &[u8]) {
+        for nal_unit in nal_units(frame) {
+            match nal_unit_type(nal_unit) {
+                Some(NAL_UNIT_TYPE_SPS) => self.sps = Some(nal_unit.to_vec()),
+                Some(NAL_UNIT_TYPE_PPS) => self.pps = Some(nal_unit.to_vec()),
+                _ => (),
+            }
+        }
+    }
+
+    /// Returns the keyframe with the parameter sets it is missing put in front
+    pub fn complete_keyframe(&self, frame: &[u8]) -> Vec<u8> {
+        let contains =
+            |nal_type| nal_units(frame).any(|nal_unit| nal_unit_type(nal_unit) == Some(nal_type));
+        let mut complete = Vec::with_capacity(frame.len());
+        for (nal_type, parameter_set) in [
+            (NAL_UNIT_TYPE_SPS, &self.sps),
+            (NAL_UNIT_TYPE_PPS, &self.pps),
+        ] {
+            if let Some(parameter_set) = parameter_set {
+                if !contains(nal_type) {
+                    complete.extend_from_slice(&[0, 0, 0, 1]);
+                    complete.extend_from_slice(parameter_set);
+                }
+            }
+        }
+        complete.extend_from_slice(frame);
+        complete
+    }
+}
+
+/// The parts of an H.264 sequence parameter set describing the stream format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceParameterSet {
+    pub profile_idc: u8,
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl SequenceParameterSet {
+    /// Parses the SPS NAL unit, starting with the NAL unit header
+    pub fn parse(nal_unit: &[u8]) -> Option<Self> {
+        let rbsp = remove_emulation_prevention(nal_unit.get(1..)?);
+        let mut r = BitReader::new(&rbsp);
+
+        let profile_idc = r.read_bits(8)? as u8;
+        let constraint_flags = r.read_bits(8)? as u8;
+        let level_idc = r.read_bits(8)? as u8;
+        let _seq_parameter_set_id = r.read_ue()?;
+
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane = false;
+        if matches!(
+            profile_idc,
+            100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+        ) {
+            chroma_format_idc = r.read_ue()?;
+            if chroma_format_idc == 3 {
+                separate_colour_plane = r.read_bit()?;
+            }
+            let _bit_depth_luma_minus8 = r.read_ue()?;
+            let _bit_depth_chroma_minus8 = r.read_ue()?;
+            let _qpprime_y_zero_transform_bypass = r.read_bit()?;
+            if r.read_bit()? {
+                let scaling_lists = if chroma_format_idc == 3 { 12 } else { 8 };
+                for i in 0..scaling_lists {
+                    if r.read_bit()? {
+                        r.skip_scaling_list(if i < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+
+        let _log2_max_frame_num_minus4 = r.read_ue()?;
+        match r.read_ue()? {
+            0 => {
+                let _log2_max_pic_order_cnt_lsb_minus4 = r.read_ue()?;
+            }
+            1 => {
+                let _delta_pic_order_always_zero = r.read_bit()?;
+                let _offset_for_non_ref_pic = r.read_se()?;
+                let _offset_for_top_to_bottom_field = r.read_se()?;
+                let num_ref_frames_in_pic_order_cnt_cycle = r.read_ue()?;
+                if num_ref_frames_in_pic_order_cnt_cycle > 255 {
+                    return None;
+                }
+                for _ in 0..num_ref_frames_in_pic_order_cnt_cycle {
+                    let _offset_for_ref_frame = r.read_se()?;
+                }
+            }
+            _ => (),
+        }
+        let _max_num_ref_frames = r.read_ue()?;
+        let _gaps_in_frame_num_value_allowed = r.read_bit()?;
+        let pic_width_in_mbs_minus1 = r.read_ue()?;
+        let pic_height_in_map_units_minus1 = r.read_ue()?;
+        let frame_mbs_only = r.read_bit()?;
+        if !frame_mbs_only {
+            let _mb_adaptive_frame_field = r.read_bit()?;
+        }
+        let _direct_8x8_inference = r.read_bit()?;
+        let (crop_left, crop_right, crop_top, crop_bottom) = if r.read_bit()? {
+            (r.read_ue()?, r.read_ue()?, r.read_ue()?, r.read_ue()?)
+        } else {
+            (0, 0, 0, 0)
+        };
+
+        // The sizes are Exp-Golomb codes of up to 32 bits, corrupted ones
+        // must not overflow
+        let field_factor = if frame_mbs_only { 1 } else { 2 };
+        let (crop_unit_x, crop_unit_y) = match (chroma_format_idc, separate_colour_plane) {
+            (0, _) | (3, true) => (1, field_factor),
+            (1, _) => (2, 2 * field_factor),
+            (2, _) => (2, field_factor),
+            _ => (1, field_factor),
+        };
+        let crop_width = crop_left
+            .checked_add(crop_right)?
+            .checked_mul(crop_unit_x)?;
+        let crop_height = crop_top
+            .checked_add(crop_bottom)?
+            .checked_mul(crop_unit_y)?;
+        let width = pic_width_in_mbs_minus1
+            .checked_add(1)?
+            .checked_mul(16)?
+            .checked_sub(crop_width)?;
+        let height = pic_height_in_map_units_minus1
+            .checked_add(1)?
+            .checked_mul(16 * field_factor)?
+            .checked_sub(crop_height)?;
+
+        Some(Self {
+            profile_idc,
+            constraint_flags,
+            level_idc,
+            width,
+            height,
+        })
+    }
+
+    pub fn profile_name(&self) -> String {
+        match self.profile_idc {
+            66 if self.constraint_flags & 0x40 != 0 => "constrained-baseline".to_string(),
+            66 => "baseline".to_string(),
+            77 => "main".to_string(),
+            88 => "extended".to_string(),
+            100 => "high".to_string(),
+            110 => "high-10".to_string(),
+            122 => "high-4:2:2".to_string(),
+            244 => "high-4:4:4".to_string(),
+            profile_idc => format!("profile-{}", profile_idc),
+        }
+    }
+}
+
+/// Removes the emulation prevention bytes (`00 00 03`) of a NAL unit payload
+fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.data.get(self.position / 8)?;
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+        Some(bit == 1)
+    }
+
+    fn read_bits(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u32;
+        }
+        Some(value)
+    }
+
+    /// Unsigned Exp-Golomb code
+    fn read_ue(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+        Some((1 << leading_zeros) - 1 + self.read_bits(leading_zeros)?)
+    }
+
+    /// Signed Exp-Golomb code
+    fn read_se(&mut self) -> Option<i32> {
+        let code = self.read_ue()? as i64;
+        let value = if code % 2 == 1 {
+            (code + 1) / 2
+        } else {
+            -(code / 2)
+        };
+        Some(value as i32)
+    }
+
+    fn skip_scaling_list(&mut self, size: usize) -> Option<()> {
+        let mut last_scale = 8;
+        let mut next_scale = 8;
+        for _ in 0..size {
+            if next_scale != 0 {
+                let delta_scale = self.read_se()?;
+                if !(-128..=127).contains(&delta_scale) {
+                    return None;
+                }
+                next_scale = (last_scale + delta_scale + 256) % 256;
+            }
+            if next_scale != 0 {
+                last_scale = next_scale;
+            }
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SPS of a 1920x1080 main profile stream, coded as 1920x1088 with the
+    /// bottom 8 rows cropped and with emulation prevention bytes
+    const SPS_1080P: &[u8] = &[
+        0x67, 0x4d, 0x00, 0x2a, 0x9d, 0xa8, 0x1e, 0x00, 0x89, 0xf9, 0x66, 0xe0, 0x20, 0x20, 0x28,
+        0x00, 0x00, 0x03, 0x00, 0x08, 0x00, 0x00, 0x03, 0x01, 0x94, 0x20,
+    ];
+    /// SPS of a 1280x720 high profile stream
+    const SPS_720P: &[u8] = &[
+        0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50, 0x05, 0xba, 0x10, 0x00, 0x00, 0x03, 0x00,
+        0x10, 0x00, 0x00, 0x03, 0x03, 0xc0, 0xf1, 0x83, 0x19, 0x60,
+    ];
+
+    #[test]
+    fn parses_the_format_of_real_sps() {
+        let sps = SequenceParameterSet::parse(SPS_1080P).unwrap();
+        assert_eq!((sps.width, sps.height), (1920, 1080));
+        assert_eq!(sps.level_idc, 42);
+        assert_eq!(sps.profile_name(), "main");
+
+        let sps = SequenceParameterSet::parse(SPS_720P).unwrap();
+        assert_eq!((sps.width, sps.height), (1280, 720));
+        assert_eq!(sps.profile_name(), "high");
+    }
+
+    #[test]
+    fn finds_the_sps_in_front_of_a_keyframe() {
+        let mut frame = vec![0, 0, 0, 1];
+        frame.extend_from_slice(SPS_720P);
+        frame.extend_from_slice(&[0, 0, 0, 1, 0x68, 0xeb, 0xe3, 0xcb, 0x22, 0xc0]);
+        frame.extend_from_slice(&[0, 0, 1, 0x65, 0x88, 0x84]);
+        let sps = find_sps(&frame).unwrap();
+        assert_eq!((sps.width, sps.height), (1280, 720));
+        assert!(is_keyframe(&frame));
+    }
+
+    #[test]
+    fn rejects_truncated_sps() {
+        // The frame cropping ends in the 11th byte, the VUI after it is not parsed
+        for length in 0..11 {
+            assert_eq!(SequenceParameterSet::parse(&SPS_1080P[..length]), None);
+        }
+    }
+
+    #[test]
+    fn rejects_sizes_overflowing() {
+        // pic_width_in_mbs_minus1 is 2^32 - 2
+        let sps = [
+            0x67, 0x42, 0x00, 0x1e, 0xdc, 0x00, 0x00, 0x03, 0x00, 0x03, 0xff, 0xff, 0xff, 0xff,
+            0x90,
+        ];
+        assert_eq!(SequenceParameterSet::parse(&sps), None);
+    }
+
+    #[test]
+    fn rejects_cropping_more_than_the_picture() {
+        // 16x16 picture with 18 columns cropped on the left
+        let sps = [0x67, 0x42, 0x00, 0x1e, 0xf4, 0xf8, 0xaf];
+        assert_eq!(SequenceParameterSet::parse(&sps), None);
+    }
+}
@@ -0,0 +1,345 @@
+use crate::h264;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+/// Frames further behind the next expected frame are not late but numbered
+/// by a publisher that restarted, or by the backup publisher taking over
+const MAX_BACKWARD_JUMP: i32 = 64;
+
+/// Frame waiting in the jitter buffer
+pub struct BufferedFrame {
+    pub frame_num: i32,
+    pub temporal_layer: u8,
+    pub frame: Vec<u8>,
+    /// Time at which the frame is due to be shown
+    pub playout: Instant,
+    /// Whether the frame was recovered after it was lost
+    pub recovered: bool,
+}
+
+/// Outcome of inserting a frame into the jitter buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Insertion {
+    Buffered,
+    /// Buffered, but the playout time of the frame has already passed
+    Late,
+    /// Dropped because it is a duplicate or the frames following it were
+    /// already released
+    Discarded,
+}
+
+/// Jitter buffer of one video stream.
+///
+/// Frames are released in `frame_num` order. The next expected frame is
+/// released right away, with its playout time for the sink to pace the output.
+/// Frames after a gap are held until their playout time, giving the missing
+/// frames until then to arrive out of order.
+///
+/// The playout time of a frame is its source timestamp mapped to the local
+/// clock plus the playout delay. The mapping is anchored on the frame with the
+/// shortest transit seen, so that clock offsets between publisher and
+/// subscriber do not matter. Frames without source timestamp are due the
+/// playout delay after their arrival.
+///
+/// A keyframe behind the next expected frame, or any frame far behind it,
+/// starts the buffer over instead of being discarded.
+pub struct JitterBuffer {
+    playout_delay: Duration,
+    /// Arrival time and source timestamp of the frame with the shortest transit
+    anchor: Option<(Instant, Duration)>,
+    next_frame_num: Option<i32>,
+    frames: BTreeMap<i32, BufferedFrame>,
+}
+
+impl JitterBuffer {
+    pub fn new(playout_delay: Duration) -> Self {
+        Self {
+            playout_delay,
+            anchor: None,
+            next_frame_num: None,
+            frames: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        frame_num: i32,
+        temporal_layer: u8,
+        source_timestamp: Option<Duration>,
+        frame: &[u8],
+        now: Instant,
+    ) -> Insertion {
+        if self.restarts(frame_num, frame) {
+            *self = Self::new(self.playout_delay);
+        }
+        let playout = self.playout_time(source_timestamp, now);
+        self.insert_with_playout(frame_num, temporal_layer, frame, playout, false, now)
+    }
+
+    /// Inserts a frame recovered after it was lost. It is due right away.
+    pub fn insert_recovered(
+        &mut self,
+        frame_num: i32,
+        temporal_layer: u8,
+        frame: &[u8],
+        now: Instant,
+    ) -> Insertion {
+        self.insert_with_playout(frame_num, temporal_layer, frame, now, true, now)
+    }
+
+    fn insert_with_playout(
+        &mut self,
+        frame_num: i32,
+        temporal_layer: u8,
+        frame: &[u8],
+        playout: Instant,
+        recovered: bool,
+        now: Instant,
+    ) -> Insertion {
+        let is_released = self
+            .next_frame_num
+            .map_or(false, |next| frame_num.wrapping_sub(next) < 0);
+        if is_released || self.frames.contains_key(&frame_num) {
+            return Insertion::Discarded;
+        }
+
+        self.frames.insert(
+            frame_num,
+            BufferedFrame {
+                frame_num,
+                temporal_layer,
+                frame: frame.to_vec(),
+                playout,
+                recovered,
+            },
+        );
+        if playout < now {
+            Insertion::Late
+        } else {
+            Insertion::Buffered
+        }
+    }
+
+    /// Whether the frame belongs to a stream numbered anew
+    fn restarts(&self, frame_num: i32, frame: &[u8]) -> bool {
+        let Some(next) = self.next_frame_num else {
+            return false;
+        };
+        let behind = next.wrapping_sub(frame_num);
+        behind > MAX_BACKWARD_JUMP || (behind > 0 && h264::is_keyframe(frame))
+    }
+
+    fn playout_time(&mut self, source_timestamp: Option<Duration>, now: Instant) -> Instant {
+        let Some(timestamp) = source_timestamp else {
+            return now + self.playout_delay;
+        };
+        let (anchor_instant, anchor_timestamp) = *self.anchor.get_or_insert((now, timestamp));
+        let expected_arrival = if timestamp >= anchor_timestamp {
+            anchor_instant.checked_add(timestamp - anchor_timestamp)
+        } else {
+            anchor_instant.checked_sub(anchor_timestamp - timestamp)
+        };
+        match expected_arrival {
+            Some(expected_arrival) if expected_arrival <= now => {
+                expected_arrival + self.playout_delay
+            }
+            _ => {
+                // Faster than the anchor frame, the transit got shorter
+                self.anchor = Some((now, timestamp));
+                now + self.playout_delay
+            }
+        }
+    }
+
+    /// Time at which the first buffered frame is due. Frames held after a gap
+    /// are released by calling `pop` at that time, even if no other frame
+    /// arrives until then.
+    pub fn next_playout(&self) -> Option<Instant> {
+        self.frames
+            .first_key_value()
+            .map(|(_, frame)| frame.playout)
+    }
+
+    /// Releases the next frame if it is the expected one or if it is due.
+    /// Frames missing before a due frame are given up.
+    pub fn pop(&mut self, now: Instant) -> Option<BufferedFrame> {
+        let (&frame_num, frame) = self.frames.first_key_value()?;
+        let expected = self.next_frame_num.unwrap_or(frame_num);
+        if frame_num != expected && frame.playout > now {
+            return None;
+        }
+        self.next_frame_num = Some(frame_num.wrapping_add(1));
+        self.frames.remove(&frame_num)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_millis(100);
+    const INTERVAL: Duration = Duration::from_millis(40);
+
+    fn released(jitter_buffer: &mut JitterBuffer, now: Instant) -> Vec<i32> {
+        std::iter::from_fn(|| jitter_buffer.pop(now))
+            .map(|frame| frame.frame_num)
+            .collect()
+    }
+
+    #[test]
+    fn expected_frames_are_released_on_arrival() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(DELAY);
+        for frame_num in 0..3 {
+            assert_eq!(
+                jitter_buffer.insert(frame_num, 0, None, &[], now),
+                Insertion::Buffered
+            );
+            assert_eq!(released(&mut jitter_buffer, now), vec![frame_num]);
+        }
+        assert_eq!(jitter_buffer.next_playout(), None);
+    }
+
+    #[test]
+    fn reordered_frames_are_released_in_order() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(DELAY);
+        jitter_buffer.insert(0, 0, None, &[], now);
+        assert_eq!(released(&mut jitter_buffer, now), vec![0]);
+
+        jitter_buffer.insert(2, 0, None, &[], now);
+        jitter_buffer.insert(3, 0, None, &[], now);
+        assert!(released(&mut jitter_buffer, now).is_empty());
+        assert_eq!(jitter_buffer.next_playout(), Some(now + DELAY));
+
+        jitter_buffer.insert(1, 0, None, &[], now);
+        assert_eq!(released(&mut jitter_buffer, now), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn frames_after_a_gap_are_released_when_due() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(DELAY);
+        jitter_buffer.insert(0, 0, None, &[], now);
+        released(&mut jitter_buffer, now);
+        jitter_buffer.insert(2, 0, None, &[], now);
+
+        let due = jitter_buffer.next_playout().unwrap();
+        assert!(released(&mut jitter_buffer, due - INTERVAL).is_empty());
+        assert_eq!(released(&mut jitter_buffer, due), vec![2]);
+
+        // The missing frame is given up
+        assert_eq!(
+            jitter_buffer.insert(1, 0, None, &[], due),
+            Insertion::Discarded
+        );
+    }
+
+    #[test]
+    fn playout_follows_the_source_timestamps() {
+        let start = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(DELAY);
+        jitter_buffer.insert(0, 0, Some(Duration::ZERO), &[], start);
+        released(&mut jitter_buffer, start);
+
+        // Frame 2 is delayed in transit, it is still due the playout delay
+        // after its source timestamp
+        let arrival = start + 3 * INTERVAL;
+        jitter_buffer.insert(2, 0, Some(2 * INTERVAL), &[], arrival);
+        assert_eq!(
+            jitter_buffer.next_playout(),
+            Some(start + 2 * INTERVAL + DELAY)
+        );
+    }
+
+    #[test]
+    fn frames_arriving_after_their_playout_time_are_late() {
+        let start = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(DELAY);
+        jitter_buffer.insert(0, 0, Some(Duration::ZERO), &[], start);
+        released(&mut jitter_buffer, start);
+
+        let arrival = start + INTERVAL + DELAY + INTERVAL;
+        assert_eq!(
+            jitter_buffer.insert(1, 0, Some(INTERVAL), &[], arrival),
+            Insertion::Late
+        );
+        assert_eq!(released(&mut jitter_buffer, arrival), vec![1]);
+    }
+
+    #[test]
+    fn duplicates_and_released_frames_are_discarded() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(DELAY);
+        jitter_buffer.insert(0, 0, None, &[], now);
+        jitter_buffer.insert(2, 0, None, &[], now);
+        assert_eq!(
+            jitter_buffer.insert(2, 0, None, &[], now),
+            Insertion::Discarded
+        );
+        released(&mut jitter_buffer, now);
+        assert_eq!(
+            jitter_buffer.insert(0, 0, None, &[], now),
+            Insertion::Discarded
+        );
+    }
+
+    #[test]
+    fn recovered_frames_are_due_right_away() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(DELAY);
+        jitter_buffer.insert(0, 0, None, &[], now);
+        released(&mut jitter_buffer, now);
+        jitter_buffer.insert(2, 0, None, &[], now);
+
+        assert_eq!(
+            jitter_buffer.insert_recovered(1, 0, &[], now),
+            Insertion::Buffered
+        );
+        let frames: Vec<_> = std::iter::from_fn(|| jitter_buffer.pop(now)).collect();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].recovered);
+        assert!(!frames[1].recovered);
+    }
+
+    #[test]
+    fn keyframes_behind_the_released_frames_start_over() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(DELAY);
+        for frame_num in 100..103 {
+            jitter_buffer.insert(frame_num, 0, None, &[], now);
+        }
+        assert_eq!(released(&mut jitter_buffer, now), vec![100, 101, 102]);
+
+        // The restarted publisher numbers its frames from 0 again
+        let keyframe = [0, 0, 0, 1, 0x65, 0x88];
+        assert_eq!(
+            jitter_buffer.insert(0, 0, None, &keyframe, now),
+            Insertion::Buffered
+        );
+        jitter_buffer.insert(1, 0, None, &[], now);
+        assert_eq!(released(&mut jitter_buffer, now), vec![0, 1]);
+    }
+
+    #[test]
+    fn frames_far_behind_the_released_frames_start_over() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(DELAY);
+        jitter_buffer.insert(100, 0, None, &[], now);
+        released(&mut jitter_buffer, now);
+
+        // Frame 101 is expected next
+        assert_eq!(
+            jitter_buffer.insert(101 - MAX_BACKWARD_JUMP, 0, None, &[], now),
+            Insertion::Discarded
+        );
+        let frame_num = 100 - MAX_BACKWARD_JUMP;
+        assert_eq!(
+            jitter_buffer.insert(frame_num, 0, None, &[], now),
+            Insertion::Buffered
+        );
+        assert_eq!(released(&mut jitter_buffer, now), vec![frame_num]);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::video::Video;
+use dust_dds::subscription::{
+    data_reader::DataReader,
+    data_reader_listener::DataReaderListener,
+    sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Name of the topic carrying the keyframes of the streams of the
+/// `video_topic`. It uses the video type. A publisher writes its last
+/// keyframe when a subscriber joined, so that it can show a picture before
+/// the next keyframe is due.
+pub fn keyframe_topic_name(video_topic: &str) -> String {
+    format!("{}Keyframe", video_topic)
+}
+
+/// Keyframe including its parameter sets
+#[derive(Debug, Clone)]
+pub struct Keyframe {
+    pub user_id: i16,
+    pub frame_num: i32,
+    pub frame: Vec<u8>,
+}
+
+impl Keyframe {
+    pub fn video(&self) -> Video<'_> {
+        Video {
+            user_id: self.user_id,
+            frame_num: self.frame_num,
+            // Keyframes are always part of the base layer
+            temporal_layer: 0,
+            frame: &self.frame,
+        }
+    }
+}
+
+/// Latest keyframe of every `user_id`, shared between the keyframe listener
+/// and the video listener
+#[derive(Clone, Default)]
+pub struct KeyframeCache(Arc<Mutex<HashMap<i16, Keyframe>>>);
+
+impl KeyframeCache {
+    pub fn get(&self, user_id: i16) -> Option<Keyframe> {
+        self.0
+            .lock()
+            .expect("keyframe lock not poisoned")
+            .get(&user_id)
+            .cloned()
+    }
+
+    fn insert(&self, keyframe: Keyframe) {
+        self.0
+            .lock()
+            .expect("keyframe lock not poisoned")
+            .insert(keyframe.user_id, keyframe);
+    }
+}
+
+/// Stores all received keyframes in the cache
+pub struct KeyframeListener {
+    pub cache: KeyframeCache,
+}
+
+impl<'a> DataReaderListener<'a> for KeyframeListener {
+    type Foo = Video<'a>;
+
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
+            for sample in samples {
+                if let Ok(video) = sample.data() {
+                    self.cache.insert(Keyframe {
+                        user_id: video.user_id,
+                        frame_num: video.frame_num,
+                        frame: video.frame.to_vec(),
+                    });
+                }
+            }
+        }
+    }
+}
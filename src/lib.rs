@@ -0,0 +1,23 @@
+pub mod alert;
+pub mod annotation;
+pub mod config;
+pub mod control;
+pub mod error;
+pub mod fec;
+pub mod filter;
+pub mod fragment;
+pub mod h264;
+pub mod jitter;
+pub mod keyframe;
+pub mod ownership;
+pub mod pacing;
+pub mod pipeline;
+pub mod processor;
+pub mod ptz;
+pub mod qos;
+pub mod sender;
+pub mod simulcast;
+pub mod snapshot;
+pub mod stats;
+pub mod stream_info;
+pub mod video;
@@ -0,0 +1,446 @@
+use clap::Parser;
+use dust_dds::{
+    domain::domain_participant_factory::DomainParticipantFactory,
+    infrastructure::{
+        instance::InstanceHandle,
+        qos::QosKind,
+        status::{StatusKind, NO_STATUS},
+    },
+    subscription::{
+        data_reader::DataReader,
+        data_reader_listener::DataReaderListener,
+        sample_info::{InstanceStateKind, ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+    },
+};
+use dust_voda::{
+    config::{Config, ConfigArgs},
+    error::Error,
+    pipeline::{
+        publish_frames, push_frame, set_geometry, CapturePipelineBuilder, DecodeBranch, Preview,
+    },
+    qos::{
+        participant_qos, publisher_qos, stream_info_reader_qos, stream_info_writer_qos,
+        subscriber_qos, video_reader_qos, video_writer_qos,
+    },
+    sender::{FrameWriters, SendQueue},
+    stream_info::{
+        announce_stream_info, StreamInfo, StreamInfoCache, StreamInfoListener,
+        STREAM_INFO_TOPIC_NAME, STREAM_INFO_TYPE_NAME,
+    },
+    video::{Video, VIDEO_TYPE_NAME},
+};
+use gstreamer::prelude::*;
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+};
+
+const TILE_WIDTH: i32 = 640;
+const TILE_HEIGHT: i32 = 360;
+const SELF_VIEW_WIDTH: i32 = 320;
+const SELF_VIEW_HEIGHT: i32 = 180;
+/// Frames of a remote participant waiting to be decoded before they are dropped
+const PARTICIPANT_QUEUE_CAPACITY: usize = 8;
+
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    /// Identifier of the local camera stream. Every participant of a call
+    /// needs a different user id
+    #[arg(long, env = "VODA_USER_ID")]
+    user_id: Option<i16>,
+}
+
+/// Decoding branch of a remote participant feeding one compositor pad
+struct RemoteParticipant {
+    user_id: i16,
+    bin: gstreamer::Bin,
+    compositor_pad: gstreamer::Pad,
+}
+
+/// Shows all remote participants as a grid with the self-view as
+/// picture-in-picture in the bottom right corner
+struct Mosaic {
+    pipeline: gstreamer::Pipeline,
+    decoders: Vec<String>,
+    compositor: gstreamer::Element,
+    self_view_pad: gstreamer::Pad,
+    participants: HashMap<InstanceHandle, RemoteParticipant>,
+}
+
+impl Mosaic {
+    /// Adds the decoding branch of a remote participant to the mosaic
+    fn add_participant(
+        &mut self,
+        instance: InstanceHandle,
+        user_id: i16,
+    ) -> Result<DecodeBranch, Error> {
+        let decode = DecodeBranch::new(&self.decoders)?;
+
+        self.pipeline.add(&decode.bin)?;
+        let compositor_pad = self
+            .compositor
+            .request_pad_simple("sink_%u")
+            .expect("Compositor has request pads");
+        decode
+            .bin
+            .static_pad("src")
+            .expect("Branch has ghost src pad")
+            .link(&compositor_pad)?;
+        decode.bin.sync_state_with_parent()?;
+
+        println!("user_id {} joined", user_id);
+        let participant = RemoteParticipant {
+            user_id,
+            bin: decode.bin.clone(),
+            compositor_pad,
+        };
+        if let Some(replaced) = self.participants.insert(instance, participant) {
+            // Its worker stops once pushing into the removed branch fails
+            self.close(replaced);
+        }
+        self.layout();
+        Ok(decode)
+    }
+
+    /// Removes the branch `decode` of a remote participant, unless a new
+    /// worker of the participant replaced it already
+    fn remove_participant(&mut self, instance: InstanceHandle, decode: &DecodeBranch) {
+        let is_current = self
+            .participants
+            .get(&instance)
+            .is_some_and(|participant| participant.bin == decode.bin);
+        if !is_current {
+            return;
+        }
+        if let Some(participant) = self.participants.remove(&instance) {
+            println!("user_id {} left", participant.user_id);
+            self.close(participant);
+            self.layout();
+        }
+    }
+
+    fn close(&self, participant: RemoteParticipant) {
+        participant.bin.set_state(gstreamer::State::Null).ok();
+        self.pipeline.remove(&participant.bin).ok();
+        self.compositor
+            .release_request_pad(&participant.compositor_pad);
+    }
+
+    fn layout(&self) {
+        let mut participants: Vec<_> = self.participants.values().collect();
+        participants.sort_by_key(|p| p.user_id);
+
+        if participants.is_empty() {
+            set_geometry(&self.self_view_pad, 0, 0, TILE_WIDTH, TILE_HEIGHT);
+            return;
+        }
+
+        let columns = (participants.len() as f64).sqrt().ceil() as usize;
+        let rows = participants.len().div_ceil(columns);
+        for (index, participant) in participants.iter().enumerate() {
+            let column = (index % columns) as i32;
+            let row = (index / columns) as i32;
+            set_geometry(
+                &participant.compositor_pad,
+                column * TILE_WIDTH,
+                row * TILE_HEIGHT,
+                TILE_WIDTH,
+                TILE_HEIGHT,
+            );
+        }
+        set_geometry(
+            &self.self_view_pad,
+            columns as i32 * TILE_WIDTH - SELF_VIEW_WIDTH,
+            rows as i32 * TILE_HEIGHT - SELF_VIEW_HEIGHT,
+            SELF_VIEW_WIDTH,
+            SELF_VIEW_HEIGHT,
+        );
+        self.self_view_pad.set_property("zorder", u32::MAX);
+    }
+}
+
+/// Frame of a remote participant handed to its worker
+struct ReceivedFrame {
+    user_id: i16,
+    frame_num: i32,
+    temporal_layer: u8,
+    frame: Vec<u8>,
+}
+
+/// Hands the frames of every remote participant to a worker thread of its
+/// own, which decodes them, so that neither the DDS listener thread nor the
+/// other participants wait for a slow decoder
+struct Listener {
+    user_id: i16,
+    mosaic: Arc<Mutex<Mosaic>>,
+    stream_infos: StreamInfoCache,
+    workers: HashMap<InstanceHandle, SyncSender<ReceivedFrame>>,
+}
+
+impl Listener {
+    fn enqueue(&mut self, instance: InstanceHandle, frame: ReceivedFrame) {
+        let sender = self
+            .workers
+            .entry(instance)
+            .or_insert_with(|| {
+                spawn_worker(
+                    self.mosaic.clone(),
+                    self.stream_infos.clone(),
+                    instance,
+                    frame.user_id,
+                )
+            })
+            .clone();
+        match sender.try_send(frame) {
+            Ok(()) => (),
+            Err(TrySendError::Full(frame)) => println!(
+                "Queue of user_id {} full, dropped frame {}",
+                frame.user_id, frame.frame_num
+            ),
+            Err(TrySendError::Disconnected(frame)) => {
+                // The next frame starts a new worker
+                println!(
+                    "Worker of user_id {} stopped, dropped its frame",
+                    frame.user_id
+                );
+                self.workers.remove(&instance);
+            }
+        }
+    }
+}
+
+/// Starts the worker showing the frames of the remote participant of
+/// `instance`. It removes the participant from the mosaic when its queue is
+/// closed or pushing a frame failed.
+fn spawn_worker(
+    mosaic: Arc<Mutex<Mosaic>>,
+    stream_infos: StreamInfoCache,
+    instance: InstanceHandle,
+    user_id: i16,
+) -> SyncSender<ReceivedFrame> {
+    let (sender, receiver) = mpsc::sync_channel::<ReceivedFrame>(PARTICIPANT_QUEUE_CAPACITY);
+    std::thread::spawn(move || {
+        let added = mosaic
+            .lock()
+            .expect("mosaic lock not poisoned")
+            .add_participant(instance, user_id);
+        let mut decode = match added {
+            Ok(decode) => decode,
+            Err(e) => {
+                println!("Showing user_id {} failed: {}", user_id, e);
+                return;
+            }
+        };
+        // Caps of the stream info last applied to the appsrc
+        let mut caps = None;
+        for received in receiver.iter() {
+            if let Some(stream_info) = stream_infos.get(user_id) {
+                if caps.as_ref() != Some(&stream_info.caps) {
+                    if let Err(e) = decode.apply_stream_info(&stream_info) {
+                        println!("{}", e);
+                        continue;
+                    }
+                    caps = Some(stream_info.caps);
+                }
+            }
+            if let Err(e) = decode.update_format(&received.frame) {
+                println!("{}", e);
+                continue;
+            }
+            let video = Video {
+                user_id: received.user_id,
+                frame_num: received.frame_num,
+                temporal_layer: received.temporal_layer,
+                frame: &received.frame,
+            };
+            if let Err(e) = push_frame(&decode.appsrc, &video) {
+                println!("Decoding user_id {} stopped: {:?}", user_id, e);
+                break;
+            }
+        }
+        mosaic
+            .lock()
+            .expect("mosaic lock not poisoned")
+            .remove_participant(instance, &decode);
+        // The queue stays open until the participant is removed, so that a
+        // worker started for a new frame adds it again afterwards
+        drop(receiver);
+    });
+    sender
+}
+
+impl<'a> DataReaderListener<'a> for Listener {
+    type Foo = Video<'a>;
+
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
+            for sample in samples {
+                let sample_info = sample.sample_info();
+                let instance_handle = sample_info.instance_handle;
+                if sample_info.instance_state != InstanceStateKind::Alive {
+                    // Closing the queue lets the worker remove the participant
+                    self.workers.remove(&instance_handle);
+                    continue;
+                }
+
+                if let Ok(sample_data) = sample.data() {
+                    if sample_data.user_id == self.user_id {
+                        continue;
+                    }
+                    self.enqueue(
+                        instance_handle,
+                        ReceivedFrame {
+                            user_id: sample_data.user_id,
+                            frame_num: sample_data.frame_num,
+                            temporal_layer: sample_data.temporal_layer,
+                            frame: sample_data.frame.to_vec(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let args = Args::parse();
+    let mut config = Config::load(&args.config)?;
+    if let Some(user_id) = args.user_id {
+        config.publisher.user_id = user_id;
+    }
+    if args.config.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
+    gstreamer::init()?;
+
+    let participant_factory = DomainParticipantFactory::get_instance();
+    let participant = participant_factory.create_participant(
+        config.domain_id,
+        QosKind::Specific(participant_qos()),
+        None,
+        NO_STATUS,
+    )?;
+    let topic = participant.create_topic::<Video>(
+        &config.video_topic,
+        VIDEO_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let publisher = participant.create_publisher(
+        QosKind::Specific(publisher_qos(&config.qos.rooms)),
+        None,
+        NO_STATUS,
+    )?;
+    let writer = publisher.create_datawriter(
+        &topic,
+        QosKind::Specific(video_writer_qos(None, config.qos.framerate)),
+        None,
+        NO_STATUS,
+    )?;
+    let subscriber = participant.create_subscriber(
+        QosKind::Specific(subscriber_qos(&config.qos.rooms)),
+        None,
+        NO_STATUS,
+    )?;
+
+    let stream_info_topic = participant.create_topic::<StreamInfo>(
+        STREAM_INFO_TOPIC_NAME,
+        STREAM_INFO_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let stream_info_writer = publisher.create_datawriter(
+        &stream_info_topic,
+        QosKind::Specific(stream_info_writer_qos(None)),
+        None,
+        NO_STATUS,
+    )?;
+    let stream_infos = StreamInfoCache::default();
+    let _stream_info_reader = subscriber.create_datareader(
+        &stream_info_topic,
+        QosKind::Specific(stream_info_reader_qos(false)),
+        Some(Box::new(StreamInfoListener {
+            cache: stream_infos.clone(),
+        })),
+        &[StatusKind::DataAvailable],
+    )?;
+
+    let capture = CapturePipelineBuilder::new(Preview::Compositor("Call".to_string()))
+        .source(&config.publisher.source)
+        .sink(&config.publisher.sink)
+        .encoders(config.codec.encoders)
+        .bitrate(config.codec.bitrate)
+        .build()?;
+    let pipeline = capture.pipeline;
+    let user_id = config.publisher.user_id;
+    let display_name = config
+        .publisher
+        .display_name
+        .unwrap_or_else(|| format!("Camera {}", user_id));
+    announce_stream_info(
+        &capture.appsink,
+        stream_info_writer,
+        StreamInfo::new(user_id, display_name, config.codec.bitrate),
+    );
+    let compositor = capture.compositor.expect("Compositor preview");
+    let mosaic = Mosaic {
+        pipeline: pipeline.clone(),
+        decoders: config.codec.decoders,
+        compositor: compositor.element,
+        self_view_pad: compositor.self_view_pad,
+        participants: HashMap::new(),
+    };
+    mosaic.layout();
+
+    pipeline.set_state(gstreamer::State::Playing)?;
+
+    let queue = Arc::new(SendQueue::new(config.publisher.send_queue_capacity));
+    publish_frames(
+        &capture.appsink,
+        FrameWriters::new(writer),
+        None,
+        queue,
+        user_id,
+    );
+
+    let _reader = subscriber.create_datareader(
+        &topic,
+        QosKind::Specific(video_reader_qos(false, None)),
+        Some(Box::new(Listener {
+            user_id,
+            mosaic: Arc::new(Mutex::new(mosaic)),
+            stream_infos,
+            workers: HashMap::new(),
+        })),
+        &[StatusKind::DataAvailable],
+    )?;
+
+    // Wait until error or EOS
+    let bus = pipeline.bus().expect("pipeline has bus");
+    for msg in bus.iter_timed(gstreamer::ClockTime::NONE) {
+        match msg.view() {
+            gstreamer::MessageView::Eos(..) => break,
+            gstreamer::MessageView::Error(err) => Err(err)?,
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gstreamer::State::Null)?;
+
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use clap::Parser;
+use dust_dds::{
+    builtin_topics::{ParticipantBuiltinTopicData, PublicationBuiltinTopicData},
+    domain::domain_participant_factory::DomainParticipantFactory,
+    infrastructure::{
+        instance::InstanceHandle, qos::QosKind, qos_policy::OwnershipQosPolicyKind,
+        status::NO_STATUS,
+    },
+    subscription::{
+        data_reader::DataReader,
+        sample_info::{InstanceStateKind, ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+    },
+};
+use dust_voda::{
+    config::{Config, ConfigArgs},
+    error::Error,
+    h264::{self, SequenceParameterSet},
+    qos::{participant_qos, subscriber_qos, video_reader_qos},
+    stats::StreamStatistics,
+    video::{Video, VIDEO_TYPE_NAME},
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::{Duration, Instant},
+};
+
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    /// Time in seconds during which the video samples are measured
+    #[arg(long, default_value_t = 2.0)]
+    sample_secs: f64,
+
+    /// Keep refreshing the list
+    #[arg(long)]
+    watch: bool,
+
+    /// Print the list as JSON. With `--watch` one line per refresh
+    #[arg(long)]
+    json: bool,
+}
+
+/// What was learned from the video samples of one publication
+#[derive(Default)]
+struct Measurement {
+    user_ids: BTreeSet<i16>,
+    statistics: Option<StreamStatistics>,
+    sps: Option<SequenceParameterSet>,
+}
+
+#[derive(Serialize)]
+struct PublisherEntry {
+    user_ids: Vec<i16>,
+    codec: Option<String>,
+    resolution: Option<String>,
+    fps: Option<f64>,
+    bitrate: Option<f64>,
+    reliability: String,
+    durability: String,
+    ownership: String,
+    rooms: Vec<String>,
+    host: Option<String>,
+}
+
+fn measure(reader: &DataReader<Video>, measurements: &mut HashMap<InstanceHandle, Measurement>) {
+    if let Ok(samples) = reader.take(
+        i32::MAX,
+        ANY_SAMPLE_STATE,
+        ANY_VIEW_STATE,
+        ANY_INSTANCE_STATE,
+    ) {
+        for sample in samples {
+            let publication_handle = sample.sample_info().publication_handle;
+            let source_timestamp = sample.sample_info().source_timestamp;
+            if let Ok(video) = sample.data() {
+                let measurement = measurements.entry(publication_handle).or_default();
+                measurement.user_ids.insert(video.user_id);
+                measurement
+                    .statistics
+                    .get_or_insert_with(|| StreamStatistics::new(video.user_id))
+                    .record(video.frame_num, video.frame.len(), source_timestamp);
+                if let Some(sps) = h264::find_sps(video.frame) {
+                    measurement.sps = Some(sps);
+                }
+            }
+        }
+    }
+}
+
+fn list_publishers(
+    publication_reader: &DataReader<PublicationBuiltinTopicData>,
+    participant_reader: &DataReader<ParticipantBuiltinTopicData>,
+    video_topic: &str,
+    measurements: &HashMap<InstanceHandle, Measurement>,
+) -> Vec<PublisherEntry> {
+    let alive = &[InstanceStateKind::Alive];
+    let hosts: HashMap<_, _> = participant_reader
+        .read(i32::MAX, ANY_SAMPLE_STATE, ANY_VIEW_STATE, alive)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|sample| sample.data().ok())
+        .map(|participant| {
+            (
+                participant.key().value,
+                String::from_utf8_lossy(&participant.user_data().value).into_owned(),
+            )
+        })
+        .collect();
+
+    let mut entries: Vec<_> = publication_reader
+        .read(i32::MAX, ANY_SAMPLE_STATE, ANY_VIEW_STATE, alive)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|sample| sample.data().ok())
+        .filter(|publication| publication.topic_name() == video_topic)
+        .map(|publication| {
+            let measurement = measurements.get(&InstanceHandle::new(publication.key().value));
+            let sps = measurement.and_then(|m| m.sps);
+            let statistics = measurement.and_then(|m| m.statistics.as_ref());
+            let ownership = match publication.ownership().kind {
+                OwnershipQosPolicyKind::Shared => "shared".to_string(),
+                OwnershipQosPolicyKind::Exclusive => {
+                    format!("exclusive ({})", publication.ownership_strength().value)
+                }
+            };
+            PublisherEntry {
+                user_ids: measurement
+                    .map(|m| m.user_ids.iter().copied().collect())
+                    .unwrap_or_default(),
+                codec: sps.map(|sps| format!("h264 {}", sps.profile_name())),
+                resolution: sps.map(|sps| format!("{}x{}", sps.width, sps.height)),
+                fps: statistics.map(|s| s.fps()),
+                bitrate: statistics.map(|s| s.bitrate()),
+                reliability: format!("{:?}", publication.reliability().kind),
+                durability: format!("{:?}", publication.durability().kind),
+                ownership,
+                rooms: publication.partition().name.clone(),
+                host: hosts.get(&publication.participant_key().value).cloned(),
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| a.user_ids.cmp(&b.user_ids));
+    entries
+}
+
+fn print_table(entries: &[PublisherEntry]) {
+    println!(
+        "{:<10} {:<28} {:<10} {:>6} {:>10} {:<12} {:<16} {:<15} {:<20} HOST",
+        "USER_ID",
+        "CODEC",
+        "RESOLUTION",
+        "FPS",
+        "KBIT/S",
+        "RELIABILITY",
+        "DURABILITY",
+        "OWNERSHIP",
+        "ROOMS"
+    );
+    for entry in entries {
+        let user_ids: Vec<_> = entry.user_ids.iter().map(|id| id.to_string()).collect();
+        println!(
+            "{:<10} {:<28} {:<10} {:>6} {:>10} {:<12} {:<16} {:<15} {:<20} {}",
+            user_ids.join(","),
+            entry.codec.as_deref().unwrap_or("-"),
+            entry.resolution.as_deref().unwrap_or("-"),
+            entry
+                .fps
+                .map_or("-".to_string(), |fps| format!("{:.1}", fps)),
+            entry.bitrate.map_or("-".to_string(), |bitrate| format!(
+                "{:.0}",
+                bitrate / 1000.0
+            )),
+            entry.reliability,
+            entry.durability,
+            entry.ownership,
+            entry.rooms.join(","),
+            entry.host.as_deref().unwrap_or("-"),
+        );
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let args = Args::parse();
+    let sample_duration = Duration::try_from_secs_f64(args.sample_secs)
+        .map_err(|e| Error(format!("Invalid sample time {}: {}", args.sample_secs, e)))?;
+    let mut config = Config::load(&args.config)?;
+    if config.qos.rooms.is_empty() {
+        // Listen to the default partition and all rooms
+        config.qos.rooms = vec![String::new(), "*".to_string()];
+    }
+    if args.config.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
+    let participant_factory = DomainParticipantFactory::get_instance();
+    let participant = participant_factory.create_participant(
+        config.domain_id,
+        QosKind::Specific(participant_qos()),
+        None,
+        NO_STATUS,
+    )?;
+    let topic = participant.create_topic::<Video>(
+        &config.video_topic,
+        VIDEO_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let subscriber = participant.create_subscriber(
+        QosKind::Specific(subscriber_qos(&config.qos.rooms)),
+        None,
+        NO_STATUS,
+    )?;
+    // Readers only match writers of the same ownership kind
+    let video_readers = [
+        subscriber.create_datareader::<Video>(
+            &topic,
+            QosKind::Specific(video_reader_qos(false, None)),
+            None,
+            NO_STATUS,
+        )?,
+        subscriber.create_datareader::<Video>(
+            &topic,
+            QosKind::Specific(video_reader_qos(true, None)),
+            None,
+            NO_STATUS,
+        )?,
+    ];
+
+    let builtin_subscriber = participant.get_builtin_subscriber();
+    let publication_reader = builtin_subscriber
+        .lookup_datareader::<PublicationBuiltinTopicData>("DCPSPublication")?
+        .expect("Builtin publication reader exists");
+    let participant_reader = builtin_subscriber
+        .lookup_datareader::<ParticipantBuiltinTopicData>("DCPSParticipant")?
+        .expect("Builtin participant reader exists");
+
+    loop {
+        let mut measurements = HashMap::new();
+        let end = Instant::now() + sample_duration;
+        while Instant::now() < end {
+            for reader in &video_readers {
+                measure(reader, &mut measurements);
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        let entries = list_publishers(
+            &publication_reader,
+            &participant_reader,
+            &config.video_topic,
+            &measurements,
+        );
+        if args.json {
+            let json = if args.watch {
+                serde_json::to_string(&entries)
+            } else {
+                serde_json::to_string_pretty(&entries)
+            };
+            println!(
+                "{}",
+                json.map_err(|e| Error(format!("JSON serialization failed: {}", e)))?
+            );
+        } else {
+            if args.watch {
+                // Clear the terminal before each refresh
+                print!("\x1b[2J\x1b[H");
+            }
+            print_table(&entries);
+        }
+
+        if !args.watch {
+            break;
+        }
+    }
+
+    Ok(())
+}
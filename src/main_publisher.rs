@@ -1,104 +1,524 @@
+use clap::Parser;
 use dust_dds::{
     domain::domain_participant_factory::DomainParticipantFactory,
-    infrastructure::{error::DdsError, qos::QosKind, status::NO_STATUS},
+    infrastructure::{
+        qos::QosKind,
+        status::{StatusKind, NO_STATUS},
+    },
+};
+use dust_voda::{
+    config::{Config, ConfigArgs},
+    control::{
+        CameraControl, CameraControlListener, CameraState, CAMERA_CONTROL_CAPABILITY,
+        CAMERA_CONTROL_TOPIC_NAME, CAMERA_CONTROL_TYPE_NAME, CAMERA_STATE_TOPIC_NAME,
+        CAMERA_STATE_TYPE_NAME,
+    },
+    error::Error,
+    fec::{parity_topic_name, ParityEncoder, VideoParity, PARITY_TYPE_NAME},
+    fragment::{fragment_topic_name, Fragmenter, VideoFragment, FRAGMENT_TYPE_NAME},
+    keyframe::keyframe_topic_name,
+    pacing::Pacer,
+    pipeline::{publish_frames, CapturePipelineBuilder, Preview, MAX_TEMPORAL_LAYERS},
+    ptz::{
+        PtzControl, PtzControlListener, PTZ_CAPABILITY, PTZ_CONTROL_TOPIC_NAME,
+        PTZ_CONTROL_TYPE_NAME,
+    },
+    qos::{
+        camera_state_writer_qos, control_reader_qos, fragment_writer_qos, keyframe_writer_qos,
+        parity_writer_qos, participant_qos, publisher_qos, simulcast_info_writer_qos,
+        snapshot_reader_qos, snapshot_writer_qos, stream_info_writer_qos, subscriber_qos,
+        video_writer_qos,
+    },
+    sender::{FrameWriters, SendQueue},
+    simulcast::{
+        layer_topic_name, SimulcastInfo, MAX_LAYERS, SIMULCAST_CAPABILITY,
+        SIMULCAST_INFO_TOPIC_NAME, SIMULCAST_INFO_TYPE_NAME,
+    },
+    snapshot::{
+        SnapshotListener, SnapshotReply, SnapshotRequest, SNAPSHOT_CAPABILITY,
+        SNAPSHOT_REPLY_TOPIC_NAME, SNAPSHOT_REPLY_TYPE_NAME, SNAPSHOT_REQUEST_TOPIC_NAME,
+        SNAPSHOT_REQUEST_TYPE_NAME,
+    },
+    stream_info::{
+        announce_stream_info, StreamInfo, STREAM_INFO_TOPIC_NAME, STREAM_INFO_TYPE_NAME,
+    },
+    video::{Video, VIDEO_TYPE_NAME},
 };
 use gstreamer::prelude::*;
+use std::sync::{Arc, Mutex};
+
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    /// Identifier of the published stream. Redundant cameras filming the same
+    /// scene use the same user id
+    #[arg(long, env = "VODA_USER_ID")]
+    user_id: Option<i16>,
+
+    /// Publish with exclusive ownership and this strength. Subscribers show the
+    /// strongest alive publisher of each user id
+    #[arg(long, env = "VODA_OWNERSHIP_STRENGTH")]
+    ownership_strength: Option<i32>,
+
+    /// Name of the stream shown to the subscribers
+    #[arg(long, env = "VODA_DISPLAY_NAME")]
+    display_name: Option<String>,
+
+    /// Send a parity sample for every this many frames, so that subscribers
+    /// can recover one lost frame per group. Subscribers only use the parity
+    /// with a playout delay covering the group
+    #[arg(long, env = "VODA_FEC_GROUP_SIZE")]
+    fec_group_size: Option<u32>,
+
+    /// Split the frames into samples of at most this many bytes
+    #[arg(long, env = "VODA_MAX_FRAGMENT_SIZE")]
+    max_fragment_size: Option<usize>,
+
+    /// Limit the bandwidth to this many bit/s by pacing the writes. Frames
+    /// larger than a 20 ms burst are only spread out when fragmented with
+    /// `--max-fragment-size`. The state of the limit and of the send queue is shown by entering `stats`
+    /// on the standard input
+    #[arg(long, env = "VODA_MAX_BITRATE")]
+    max_bitrate: Option<u32>,
+
+    /// Number of frames waiting to be written before delta frames are dropped
+    #[arg(long, env = "VODA_SEND_QUEUE_CAPACITY")]
+    send_queue_capacity: Option<usize>,
+
+    /// Host name of a subscriber allowed to control the camera. Can be given
+    /// several times. The host names claimed by the requests are not
+    /// verified, this is no access control
+    #[arg(long = "control-operator")]
+    control_operators: Vec<String>,
 
-#[derive(Debug, dust_dds::topic_definition::type_support::DdsType)]
-struct Video<'a> {
-    user_id: i16,
-    frame_num: i32,
-    frame: &'a [u8],
+    /// Host name of a client allowed to request snapshots. Can be given
+    /// several times. Not verified either
+    #[arg(long = "snapshot-operator")]
+    snapshot_operators: Vec<String>,
+
+    /// Number of temporal layers, 2 or 3 let subscribers follow the stream at
+    /// 1/2 or 1/4 of the framerate. Switches to x264enc in the main profile,
+    /// which must be in the encoder preference list
+    #[arg(long, env = "VODA_TEMPORAL_LAYERS")]
+    temporal_layers: Option<u8>,
 }
-#[derive(Debug)]
-struct Error(String);
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.0)
+
+fn main() -> Result<(), Error> {
+    let args = Args::parse();
+    let mut config = Config::load(&args.config)?;
+    if let Some(user_id) = args.user_id {
+        config.publisher.user_id = user_id;
     }
-}
-impl From<gstreamer::glib::Error> for Error {
-    fn from(value: gstreamer::glib::Error) -> Self {
-        Self(format!("GStreamer error: {}", value))
+    if args.display_name.is_some() {
+        config.publisher.display_name = args.display_name;
     }
-}
-impl From<gstreamer::StateChangeError> for Error {
-    fn from(value: gstreamer::StateChangeError) -> Self {
-        Self(format!("GStreamer state change error: {}", value))
+    if args.ownership_strength.is_some() {
+        config.qos.ownership_strength = args.ownership_strength;
     }
-}
-impl From<&gstreamer::message::Error> for Error {
-    fn from(value: &gstreamer::message::Error) -> Self {
-        Self(format!("GStreamer state change error: {:?}", value))
+    if args.fec_group_size.is_some() {
+        config.publisher.fec_group_size = args.fec_group_size;
     }
-}
-impl From<DdsError> for Error {
-    fn from(value: DdsError) -> Self {
-        Self(format!("DDS error: {:?}", value))
+    if args.max_fragment_size.is_some() {
+        config.publisher.max_fragment_size = args.max_fragment_size;
+    }
+    if args.max_bitrate.is_some() {
+        config.publisher.max_bitrate = args.max_bitrate;
+    }
+    if let Some(send_queue_capacity) = args.send_queue_capacity {
+        config.publisher.send_queue_capacity = send_queue_capacity;
+    }
+    if !args.control_operators.is_empty() {
+        config.publisher.control_operators = args.control_operators;
+    }
+    if !args.snapshot_operators.is_empty() {
+        config.publisher.snapshot_operators = args.snapshot_operators;
+    }
+    if let Some(temporal_layers) = args.temporal_layers {
+        config.publisher.temporal_layers = temporal_layers;
+    }
+    if !(1..=MAX_TEMPORAL_LAYERS).contains(&config.publisher.temporal_layers) {
+        return Err(Error(format!(
+            "The number of temporal layers must be between 1 and {}",
+            MAX_TEMPORAL_LAYERS
+        )));
+    }
+    if config.publisher.simulcast_layers.len() >= MAX_LAYERS {
+        return Err(Error(format!(
+            "At most {} simulcast layers can be published besides the main stream",
+            MAX_LAYERS - 1
+        )));
+    }
+    if args.config.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
     }
-}
 
-fn main() -> Result<(), Error> {
     gstreamer::init()?;
 
-    let domain_id = 0;
     let participant_factory = DomainParticipantFactory::get_instance();
-    let participant =
-        participant_factory.create_participant(domain_id, QosKind::Default, None, NO_STATUS)?;
+    let participant = participant_factory.create_participant(
+        config.domain_id,
+        QosKind::Specific(participant_qos()),
+        None,
+        NO_STATUS,
+    )?;
     let topic = participant.create_topic::<Video>(
-        "VideoStream",
-        "Video",
+        &config.video_topic,
+        VIDEO_TYPE_NAME,
         QosKind::Default,
         None,
         NO_STATUS,
     )?;
-    let publisher = participant.create_publisher(QosKind::Default, None, NO_STATUS)?;
-    let writer = publisher.create_datawriter(&topic, QosKind::Default, None, NO_STATUS)?;
+    let publisher = participant.create_publisher(
+        QosKind::Specific(publisher_qos(&config.qos.rooms)),
+        None,
+        NO_STATUS,
+    )?;
+    let writer = publisher.create_datawriter(
+        &topic,
+        QosKind::Specific(video_writer_qos(
+            config.qos.ownership_strength,
+            config.qos.framerate,
+        )),
+        None,
+        NO_STATUS,
+    )?;
 
-    let pipeline = gstreamer::parse::launch(
-        r#"autovideosrc ! video/x-raw,framerate=[1/1,25/1],width=[1,1280],height=[1,720] ! tee name=t ! queue leaky=2 ! videoconvert ! openh264enc complexity=0 scene-change-detection=0 background-detection=0 bitrate=1280000 ! appsink name=appsink sync=false t. ! queue leaky=2 ! taginject tags="title=Publisher" ! autovideosink"#,
+    let stream_info_topic = participant.create_topic::<StreamInfo>(
+        STREAM_INFO_TOPIC_NAME,
+        STREAM_INFO_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let stream_info_writer = publisher.create_datawriter(
+        &stream_info_topic,
+        QosKind::Specific(stream_info_writer_qos(config.qos.ownership_strength)),
+        None,
+        NO_STATUS,
+    )?;
+
+    let keyframe_topic = participant.create_topic::<Video>(
+        &keyframe_topic_name(&config.video_topic),
+        VIDEO_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let keyframe_writer = publisher.create_datawriter(
+        &keyframe_topic,
+        QosKind::Specific(keyframe_writer_qos(config.qos.ownership_strength)),
+        None,
+        NO_STATUS,
+    )?;
+
+    let fragmenter = match config.publisher.max_fragment_size {
+        Some(max_size) => {
+            let fragment_topic = participant.create_topic::<VideoFragment>(
+                &fragment_topic_name(&config.video_topic),
+                FRAGMENT_TYPE_NAME,
+                QosKind::Default,
+                None,
+                NO_STATUS,
+            )?;
+            let fragment_writer = publisher.create_datawriter(
+                &fragment_topic,
+                QosKind::Specific(fragment_writer_qos(
+                    config.qos.ownership_strength,
+                    config.qos.framerate,
+                )),
+                None,
+                NO_STATUS,
+            )?;
+            Some(Fragmenter {
+                writer: fragment_writer,
+                max_size,
+            })
+        }
+        None => None,
+    };
+
+    let parity_encoder = match config.publisher.fec_group_size {
+        Some(group_size) => {
+            let parity_topic = participant.create_topic::<VideoParity>(
+                &parity_topic_name(&config.video_topic),
+                PARITY_TYPE_NAME,
+                QosKind::Default,
+                None,
+                NO_STATUS,
+            )?;
+            let parity_writer = publisher.create_datawriter(
+                &parity_topic,
+                QosKind::Specific(parity_writer_qos(config.qos.ownership_strength)),
+                None,
+                NO_STATUS,
+            )?;
+            Some(ParityEncoder::new(
+                parity_writer,
+                config.publisher.user_id,
+                group_size,
+            ))
+        }
+        None => None,
+    };
+
+    let capture = CapturePipelineBuilder::new(Preview::Window("Publisher".to_string()))
+        .source(&config.publisher.source)
+        .sink(&config.publisher.sink)
+        .encoders(config.codec.encoders)
+        .bitrate(config.codec.bitrate)
+        .layers(config.publisher.simulcast_layers.clone())
+        .temporal_layers(config.publisher.temporal_layers)
+        .build()?;
+    let pipeline = capture.pipeline;
+    let user_id = config.publisher.user_id;
+    let display_name = config
+        .publisher
+        .display_name
+        .unwrap_or_else(|| format!("Camera {}", user_id));
+    let mut stream_info = StreamInfo::new(user_id, display_name, config.codec.bitrate);
+    stream_info.temporal_layers = config.publisher.temporal_layers;
+
+    let mut layer_writers = Vec::new();
+    if !config.publisher.simulcast_layers.is_empty() {
+        for layer in 1..=config.publisher.simulcast_layers.len() {
+            let layer_topic = participant.create_topic::<Video>(
+                &layer_topic_name(&config.video_topic, layer),
+                VIDEO_TYPE_NAME,
+                QosKind::Default,
+                None,
+                NO_STATUS,
+            )?;
+            layer_writers.push(publisher.create_datawriter(
+                &layer_topic,
+                QosKind::Specific(video_writer_qos(
+                    config.qos.ownership_strength,
+                    config.qos.framerate,
+                )),
+                None,
+                NO_STATUS,
+            )?);
+        }
+
+        let simulcast_info_topic = participant.create_topic::<SimulcastInfo>(
+            SIMULCAST_INFO_TOPIC_NAME,
+            SIMULCAST_INFO_TYPE_NAME,
+            QosKind::Default,
+            None,
+            NO_STATUS,
+        )?;
+        let simulcast_info_writer = publisher.create_datawriter(
+            &simulcast_info_topic,
+            QosKind::Specific(simulcast_info_writer_qos()),
+            None,
+            NO_STATUS,
+        )?;
+        simulcast_info_writer.write(
+            &SimulcastInfo::new(
+                user_id,
+                config.codec.bitrate,
+                &config.publisher.simulcast_layers,
+            ),
+            None,
+        )?;
+        stream_info
+            .capabilities
+            .push(SIMULCAST_CAPABILITY.to_string());
+    }
+
+    // Reader of the requests addressed to the publisher, in the same rooms
+    let request_subscriber = participant.create_subscriber(
+        QosKind::Specific(subscriber_qos(&config.qos.rooms)),
+        None,
+        NO_STATUS,
     )?;
 
+    let snapshot_operators = config.publisher.snapshot_operators;
+    let _snapshot_reader = if snapshot_operators.is_empty() {
+        None
+    } else {
+        let snapshot_request_topic = participant.create_topic::<SnapshotRequest>(
+            SNAPSHOT_REQUEST_TOPIC_NAME,
+            SNAPSHOT_REQUEST_TYPE_NAME,
+            QosKind::Default,
+            None,
+            NO_STATUS,
+        )?;
+        let snapshot_reply_topic = participant.create_topic::<SnapshotReply>(
+            SNAPSHOT_REPLY_TOPIC_NAME,
+            SNAPSHOT_REPLY_TYPE_NAME,
+            QosKind::Default,
+            None,
+            NO_STATUS,
+        )?;
+        let snapshot_writer = publisher.create_datawriter(
+            &snapshot_reply_topic,
+            QosKind::Specific(snapshot_writer_qos()),
+            None,
+            NO_STATUS,
+        )?;
+        println!(
+            "Accepting snapshot requests from {}",
+            snapshot_operators.join(", ")
+        );
+        stream_info
+            .capabilities
+            .push(SNAPSHOT_CAPABILITY.to_string());
+        Some(request_subscriber.create_datareader(
+            &snapshot_request_topic,
+            QosKind::Specific(snapshot_reader_qos()),
+            Some(Box::new(SnapshotListener::new(
+                capture.snapshot_sink.clone(),
+                capture.controls.clone(),
+                snapshot_operators,
+                snapshot_writer,
+                user_id,
+            ))),
+            &[StatusKind::DataAvailable],
+        )?)
+    };
+
+    let control_operators = config.publisher.control_operators;
+    let _control_readers = if control_operators.is_empty() {
+        None
+    } else {
+        let state_topic = participant.create_topic::<CameraState>(
+            CAMERA_STATE_TOPIC_NAME,
+            CAMERA_STATE_TYPE_NAME,
+            QosKind::Default,
+            None,
+            NO_STATUS,
+        )?;
+        let state_writer = publisher.create_datawriter(
+            &state_topic,
+            QosKind::Specific(camera_state_writer_qos()),
+            None,
+            NO_STATUS,
+        )?;
+        let control_topic = participant.create_topic::<CameraControl>(
+            CAMERA_CONTROL_TOPIC_NAME,
+            CAMERA_CONTROL_TYPE_NAME,
+            QosKind::Default,
+            None,
+            NO_STATUS,
+        )?;
+        println!(
+            "Accepting camera control from {}",
+            control_operators.join(", ")
+        );
+        stream_info
+            .capabilities
+            .push(CAMERA_CONTROL_CAPABILITY.to_string());
+        stream_info.capabilities.push(PTZ_CAPABILITY.to_string());
+        let control_reader = request_subscriber.create_datareader(
+            &control_topic,
+            QosKind::Specific(control_reader_qos()),
+            Some(Box::new(CameraControlListener::new(
+                capture.controls.clone(),
+                control_operators.clone(),
+                state_writer,
+                user_id,
+                config.codec.bitrate,
+            ))),
+            &[StatusKind::DataAvailable],
+        )?;
+        let ptz_topic = participant.create_topic::<PtzControl>(
+            PTZ_CONTROL_TOPIC_NAME,
+            PTZ_CONTROL_TYPE_NAME,
+            QosKind::Default,
+            None,
+            NO_STATUS,
+        )?;
+        let ptz_reader = request_subscriber.create_datareader(
+            &ptz_topic,
+            QosKind::Specific(control_reader_qos()),
+            Some(Box::new(PtzControlListener::new(
+                capture.controls.clone(),
+                control_operators,
+                user_id,
+                config.publisher.ptz_presets,
+            ))),
+            &[StatusKind::DataAvailable],
+        )?;
+        Some((control_reader, ptz_reader))
+    };
+
+    announce_stream_info(&capture.appsink, stream_info_writer, stream_info);
+
     pipeline.set_state(gstreamer::State::Playing)?;
 
-    let bin = pipeline
-        .downcast_ref::<gstreamer::Bin>()
-        .expect("Pipeline must be bin");
-    let appsink_element = bin.by_name("appsink").expect("appsink in pipeline");
-    let appsink = appsink_element
-        .downcast_ref::<gstreamer_app::AppSink>()
-        .expect("type AppSink");
-
-    let mut i = 0;
-    appsink.set_callbacks(
-        gstreamer_app::AppSinkCallbacks::builder()
-            .new_sample(move |s| {
-                if let Ok(sample) = s.pull_sample() {
-                    let bytes = sample
-                        .buffer()
-                        .expect("buffer exists")
-                        .map_readable()
-                        .expect("readable buffer");
-
-                    let video_sample = Video {
-                        user_id: 8,
-                        frame_num: i,
-                        frame: bytes.as_slice(),
-                    };
-                    writer
-                        .write(&video_sample, None)
-                        .expect("Sample could not be written");
-
-                    i += 1;
-                    println!("Wrote sample {:?}", i);
-                    use std::io::{self, Write};
-                    io::stdout().flush().ok();
+    let pacer = config
+        .publisher
+        .max_bitrate
+        .map(|max_bitrate| Arc::new(Mutex::new(Pacer::new(max_bitrate))));
+
+    let queue = Arc::new(SendQueue::new(config.publisher.send_queue_capacity));
+
+    let room_publisher = publisher.clone();
+    let room_request_subscriber = request_subscriber.clone();
+    let stats_pacer = pacer.clone();
+    let stats_queue = queue.clone();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            let words: Vec<_> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["stats"] => {
+                    println!("{}", stats_queue.summary());
+                    match &stats_pacer {
+                        Some(pacer) => println!(
+                            "{}",
+                            pacer.lock().expect("pacer lock not poisoned").summary()
+                        ),
+                        None => println!("No bandwidth limit"),
+                    }
                 }
+                ["room", names @ ..] => {
+                    let room = names.join(" ");
+                    let rooms: Vec<_> = names.iter().map(|name| name.to_string()).collect();
+                    match room_publisher.set_qos(QosKind::Specific(publisher_qos(&rooms))) {
+                        Ok(()) => println!("Moved to room {:?}", room),
+                        Err(e) => println!("Moving to room {:?} failed: {:?}", room, e),
+                    }
+                    let qos = QosKind::Specific(subscriber_qos(&rooms));
+                    if let Err(e) = room_request_subscriber.set_qos(qos) {
+                        println!(
+                            "Moving the request reader to room {:?} failed: {:?}",
+                            room, e
+                        );
+                    }
+                }
+                _ => println!(
+                    "Unknown command {:?}, expected `room <name>` or `stats`",
+                    line.trim()
+                ),
+            }
+        }
+    });
 
-                Ok(gstreamer::FlowSuccess::Ok)
-            })
-            .build(),
+    let writers = FrameWriters::new(writer)
+        .fragmenter(fragmenter)
+        .keyframe_writer(Some(keyframe_writer))
+        .parity_encoder(parity_encoder);
+    publish_frames(
+        &capture.appsink,
+        writers,
+        pacer.clone(),
+        queue,
+        user_id,
+        config.publisher.temporal_layers,
     );
+    for (layer_sink, layer_writer) in capture.layer_sinks.iter().zip(layer_writers) {
+        publish_frames(
+            layer_sink,
+            FrameWriters::new(layer_writer),
+            pacer.clone(),
+            Arc::new(SendQueue::new(config.publisher.send_queue_capacity)),
+            user_id,
+            1,
+        );
+    }
 
     // Wait until error or EOS
     let bus = pipeline.bus().expect("pipeline has bus");
@@ -0,0 +1,184 @@
+use clap::Parser;
+use dust_dds::{
+    domain::domain_participant_factory::DomainParticipantFactory,
+    infrastructure::{qos::QosKind, status::NO_STATUS},
+    subscription::sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+};
+use dust_voda::{
+    config::{Config, ConfigArgs},
+    error::Error,
+    qos::{
+        participant_qos, publisher_qos, snapshot_reader_qos, snapshot_writer_qos, subscriber_qos,
+    },
+    snapshot::{
+        ImageFormat, SnapshotReply, SnapshotRequest, SNAPSHOT_REPLY_TOPIC_NAME,
+        SNAPSHOT_REPLY_TYPE_NAME, SNAPSHOT_REQUEST_TOPIC_NAME, SNAPSHOT_REQUEST_TYPE_NAME,
+    },
+};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    /// Stream to take the snapshot of
+    #[arg(long, env = "VODA_USER_ID")]
+    user_id: i16,
+
+    /// `jpeg` or `png`. Defaults to the extension of the output file
+    #[arg(long)]
+    format: Option<String>,
+
+    /// Width of the image, given together with the height. The camera
+    /// resolution by default
+    #[arg(long, default_value_t = 0)]
+    width: u32,
+
+    /// Height of the image, given together with the width. The camera
+    /// resolution by default
+    #[arg(long, default_value_t = 0)]
+    height: u32,
+
+    /// Time in seconds to wait for the publisher and its reply
+    #[arg(long, default_value_t = 5.0)]
+    timeout_secs: f64,
+
+    /// File the image is saved to
+    output: PathBuf,
+}
+
+fn main() -> Result<(), Error> {
+    let args = Args::parse();
+    let timeout = Duration::try_from_secs_f64(args.timeout_secs)
+        .map_err(|e| Error(format!("Invalid timeout {}: {}", args.timeout_secs, e)))?;
+    let config = Config::load(&args.config)?;
+    if args.config.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+    let format = match &args.format {
+        Some(format) => ImageFormat::parse(format)?,
+        None => match args.output.extension().and_then(|e| e.to_str()) {
+            Some("png") => ImageFormat::Png,
+            _ => ImageFormat::Jpeg,
+        },
+    };
+
+    let participant_factory = DomainParticipantFactory::get_instance();
+    let participant = participant_factory.create_participant(
+        config.domain_id,
+        QosKind::Specific(participant_qos()),
+        None,
+        NO_STATUS,
+    )?;
+    let request_topic = participant.create_topic::<SnapshotRequest>(
+        SNAPSHOT_REQUEST_TOPIC_NAME,
+        SNAPSHOT_REQUEST_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let reply_topic = participant.create_topic::<SnapshotReply>(
+        SNAPSHOT_REPLY_TOPIC_NAME,
+        SNAPSHOT_REPLY_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let publisher = participant.create_publisher(
+        QosKind::Specific(publisher_qos(&config.qos.rooms)),
+        None,
+        NO_STATUS,
+    )?;
+    let subscriber = participant.create_subscriber(
+        QosKind::Specific(subscriber_qos(&config.qos.rooms)),
+        None,
+        NO_STATUS,
+    )?;
+    let request_writer = publisher.create_datawriter(
+        &request_topic,
+        QosKind::Specific(snapshot_writer_qos()),
+        None,
+        NO_STATUS,
+    )?;
+    let reply_reader = subscriber.create_datareader::<SnapshotReply>(
+        &reply_topic,
+        QosKind::Specific(snapshot_reader_qos()),
+        None,
+        NO_STATUS,
+    )?;
+
+    let deadline = Instant::now() + timeout;
+    // Requests written before a publisher matched would be lost, and so
+    // would its reply before the publisher matched the reply reader
+    while request_writer
+        .get_publication_matched_status()?
+        .current_count
+        == 0
+        || reply_reader
+            .get_subscription_matched_status()?
+            .current_count
+            == 0
+    {
+        if Instant::now() > deadline {
+            return Err(Error(
+                "No publisher answering snapshot requests".to_string(),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let operator = gethostname::gethostname().to_string_lossy().into_owned();
+    let client = format!("{}/{}", operator, std::process::id());
+    let request = SnapshotRequest {
+        user_id: args.user_id,
+        client: client.clone(),
+        operator,
+        request_id: 1,
+        format: format.as_str().to_string(),
+        width: args.width,
+        height: args.height,
+    };
+    request_writer.write(&request, None)?;
+
+    loop {
+        if Instant::now() > deadline {
+            return Err(Error(format!(
+                "No snapshot of user_id {} received",
+                args.user_id
+            )));
+        }
+        let replies = reply_reader
+            .take(
+                i32::MAX,
+                ANY_SAMPLE_STATE,
+                ANY_VIEW_STATE,
+                ANY_INSTANCE_STATE,
+            )
+            .unwrap_or_default();
+        let reply = replies
+            .iter()
+            .filter_map(|sample| sample.data().ok())
+            .find(|reply| reply.client == client && reply.request_id == request.request_id);
+        match reply {
+            Some(reply) if reply.error.is_empty() => {
+                std::fs::write(&args.output, &reply.image).map_err(|e| {
+                    Error(format!("Writing {} failed: {}", args.output.display(), e))
+                })?;
+                println!(
+                    "Saved snapshot of user_id {} to {} ({} bytes)",
+                    args.user_id,
+                    args.output.display(),
+                    reply.image.len()
+                );
+                return Ok(());
+            }
+            Some(reply) => return Err(Error(format!("Snapshot failed: {}", reply.error))),
+            None => std::thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
@@ -1,127 +1,1405 @@
+use clap::Parser;
 use dust_dds::{
     domain::domain_participant_factory::DomainParticipantFactory,
     infrastructure::{
-        error::DdsError,
+        instance::InstanceHandle,
         qos::QosKind,
-        status::{StatusKind, NO_STATUS},
+        status::{LivelinessChangedStatus, RequestedDeadlineMissedStatus, StatusKind, NO_STATUS},
     },
     subscription::{
+        data_reader::DataReader,
         data_reader_listener::DataReaderListener,
-        sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+        sample_info::{SampleInfo, ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
     },
 };
+use dust_voda::{
+    alert::{AlertKind, Alerts, StreamAlert, STREAM_ALERT_TOPIC_NAME, STREAM_ALERT_TYPE_NAME},
+    annotation::{
+        Annotation, AnnotationCache, AnnotationListener, AnnotationOverlay, ANNOTATION_TOPIC_NAME,
+        ANNOTATION_TYPE_NAME,
+    },
+    config::{Config, ConfigArgs, Overflow, StreamQueueConfig},
+    control::{
+        CameraControl, CameraState, CameraStateListener, ControlCommand, CAMERA_CONTROL_TOPIC_NAME,
+        CAMERA_CONTROL_TYPE_NAME, CAMERA_STATE_TOPIC_NAME, CAMERA_STATE_TYPE_NAME,
+    },
+    error::Error,
+    fec::{
+        parity_topic_name, FecDecoder, ParityCache, ParityListener, Recovery, VideoParity,
+        PARITY_TYPE_NAME,
+    },
+    filter::VideoFilter,
+    fragment::{fragment_topic_name, Reassembler, VideoFragment, FRAGMENT_TYPE_NAME},
+    h264,
+    jitter::{Insertion, JitterBuffer},
+    keyframe::{keyframe_topic_name, KeyframeCache, KeyframeListener},
+    ownership::OwnershipMonitor,
+    pipeline::{
+        connect_navigation, push_frame, push_frame_at, DecodeBranch, PlaybackPipeline,
+        PlaybackPipelineBuilder,
+    },
+    processor::{
+        create_processor, spawn_processor, ProcessorEvent, ProcessorOutput,
+        PROCESSOR_EVENT_TOPIC_NAME, PROCESSOR_EVENT_TYPE_NAME,
+    },
+    ptz::{PtzCommand, PtzControl, PtzSender, PTZ_CONTROL_TOPIC_NAME, PTZ_CONTROL_TYPE_NAME},
+    qos::{
+        camera_state_reader_qos, control_writer_qos, fragment_reader_qos, keyframe_reader_qos,
+        parity_reader_qos, participant_qos, publisher_qos, simulcast_info_reader_qos,
+        stream_info_reader_qos, subscriber_qos, video_reader_qos,
+    },
+    simulcast::{
+        layer_topic_name, LayerDecision, LayerSelection, LayerSwitch, SimulcastInfo,
+        SimulcastInfoCache, SimulcastInfoListener, MAX_LAYERS, SIMULCAST_INFO_TOPIC_NAME,
+        SIMULCAST_INFO_TYPE_NAME,
+    },
+    stats::StreamStatistics,
+    stream_info::{
+        StreamInfo, StreamInfoCache, StreamInfoListener, STREAM_INFO_TOPIC_NAME,
+        STREAM_INFO_TYPE_NAME,
+    },
+    video::{Video, VIDEO_TYPE_NAME},
+};
 use gstreamer::prelude::*;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        mpsc::{self, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
-#[derive(Debug, dust_dds::topic_definition::type_support::DdsType)]
-struct Video<'a> {
-    user_id: i16,
-    frame_num: i32,
-    frame: &'a [u8],
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    /// Only show the strongest alive publisher of each user id and fail over
+    /// to the next one when it is lost. Requires publishers with an ownership
+    /// strength. `--exclusive-ownership=false` turns it off if configured
+    #[arg(long, env = "VODA_EXCLUSIVE_OWNERSHIP", num_args = 0..=1, default_missing_value = "true")]
+    exclusive_ownership: Option<bool>,
+
+    /// Only show the stream of this user id. Can be given several times.
+    /// All streams are shown by default
+    #[arg(long = "user-id", env = "VODA_USER_IDS", value_delimiter = ',')]
+    user_ids: Vec<i16>,
+
+    /// Only decode keyframes, or all frames with `--keyframes-only=false`
+    #[arg(long, env = "VODA_KEYFRAMES_ONLY", num_args = 0..=1, default_missing_value = "true")]
+    keyframes_only: Option<bool>,
+
+    /// Minimum time in milliseconds between two shown frames of a stream
+    #[arg(long, env = "VODA_MINIMUM_SEPARATION_MS")]
+    minimum_separation_ms: Option<u64>,
+
+    /// Show the statistics overlay from the start, or hide it with
+    /// `--stats-overlay=false`. The overlay can be toggled while running by
+    /// entering `stats` on the standard input
+    #[arg(long, env = "VODA_STATS_OVERLAY", num_args = 0..=1, default_missing_value = "true")]
+    stats_overlay: Option<bool>,
+
+    /// Buffer frames for this many milliseconds to reorder them and absorb
+    /// jitter. Frames are shown on arrival by default
+    #[arg(long, env = "VODA_PLAYOUT_DELAY_MS")]
+    playout_delay_ms: Option<u64>,
+
+    /// Shell command run for every alert about a stalled or lost stream.
+    /// Requires the framerate to be configured
+    #[arg(long, env = "VODA_ALERT_COMMAND")]
+    alert_command: Option<String>,
+
+    /// Number of frames of a stream waiting to be decoded before frames are
+    /// dropped. Applies to the streams without their own queue configuration
+    #[arg(long, env = "VODA_STREAM_QUEUE_CAPACITY")]
+    stream_queue_capacity: Option<usize>,
+
+    /// Simulcast layer to show, 0 is the main stream and larger layers are
+    /// smaller
+    #[arg(long, env = "VODA_LAYER")]
+    layer: Option<usize>,
+
+    /// Choose the layer from the viewport height and move to smaller layers
+    /// while frames are not shown in time. `--auto-layer=false` keeps the
+    /// configured layer
+    #[arg(long, env = "VODA_AUTO_LAYER", num_args = 0..=1, default_missing_value = "true")]
+    auto_layer: Option<bool>,
+
+    /// Height of the video window in pixels, used to choose the layer
+    #[arg(long)]
+    viewport_height: Option<u32>,
+
+    /// Only decode the temporal layers up to this one, 0 being the base layer
+    /// at the lowest framerate
+    #[arg(long, env = "VODA_MAX_TEMPORAL_LAYER")]
+    max_temporal_layer: Option<u8>,
+
+    /// Draw the annotations of the streams. The annotations of a producer can
+    /// be toggled while running by entering `annotations <producer>` on the
+    /// standard input. `--annotations=false` does not subscribe to them
+    #[arg(long, env = "VODA_ANNOTATIONS", num_args = 0..=1, default_missing_value = "true")]
+    annotations: Option<bool>,
+
+    /// Do not draw the annotations of this producer until toggled. Can be
+    /// given several times
+    #[arg(long = "hide-annotations")]
+    hidden_annotation_producers: Vec<String>,
+
+    /// Analyze the decoded frames with this frame processor, e.g. `motion`.
+    /// Can be given several times
+    #[arg(long = "processor")]
+    processors: Vec<String>,
 }
 
-#[derive(Debug)]
-struct Error(String);
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.0)
+/// Receive statistics of all streams. The lock is only held to count, never
+/// while a frame is decoded.
+#[derive(Clone, Default)]
+struct Statistics(Arc<Mutex<BTreeMap<i16, StreamStatistics>>>);
+
+impl Statistics {
+    fn update<T>(&self, user_id: i16, f: impl FnOnce(&mut StreamStatistics) -> T) -> T {
+        let mut statistics = self.0.lock().expect("statistics lock not poisoned");
+        f(statistics
+            .entry(user_id)
+            .or_insert_with(|| StreamStatistics::new(user_id)))
     }
-}
-impl From<gstreamer::glib::Error> for Error {
-    fn from(value: gstreamer::glib::Error) -> Self {
-        Self(format!("GStreamer error: {}", value))
+
+    /// Text of the statistics overlay, listing all streams
+    fn overlay_text(&self) -> String {
+        self.0
+            .lock()
+            .expect("statistics lock not poisoned")
+            .values()
+            .map(StreamStatistics::overlay_text)
+            .collect::<Vec<_>>()
+            .join("\n\n")
     }
 }
-impl From<gstreamer::StateChangeError> for Error {
-    fn from(value: gstreamer::StateChangeError) -> Self {
-        Self(format!("GStreamer state change error: {}", value))
+
+/// Settings and caches shared by the workers of all streams
+struct WorkerContext {
+    playback: Arc<PlaybackPipeline>,
+    /// Filter every stream starts with
+    filter: VideoFilter,
+    statistics: Statistics,
+    stream_infos: StreamInfoCache,
+    keyframes: KeyframeCache,
+    playout_delay: Option<Duration>,
+    parities: ParityCache,
+    selection: LayerSelection,
+    simulcast_infos: SimulcastInfoCache,
+}
+
+/// Decodes and shows the frames of one stream on the worker thread of the
+/// stream. The decode branch of the stream is added to the playback pipeline
+/// once its first frame passes the filter.
+struct StreamWorker {
+    user_id: i16,
+    context: Arc<WorkerContext>,
+    decode: Option<DecodeBranch>,
+    filter: VideoFilter,
+    /// Caps of the stream info last applied to the appsrc
+    caps: Option<String>,
+    jitter_buffer: Option<JitterBuffer>,
+    fec_decoder: Option<FecDecoder>,
+    /// Whether the parity of the stream is ignored because the playout delay
+    /// is too short to wait for the recovered frames
+    fec_refused: bool,
+    layer_switch: LayerSwitch,
+    /// Whether a frame of the stream was received before
+    started: bool,
+    /// Publication of the frames shown last
+    publication: Option<InstanceHandle>,
+    /// Whether the decode branch stopped taking frames, which ends the worker
+    failed: bool,
+}
+
+impl StreamWorker {
+    fn new(user_id: i16, context: Arc<WorkerContext>) -> Self {
+        Self {
+            user_id,
+            filter: context.filter.clone(),
+            context,
+            decode: None,
+            caps: None,
+            jitter_buffer: None,
+            fec_decoder: None,
+            fec_refused: false,
+            layer_switch: LayerSwitch::new(Instant::now()),
+            started: false,
+            publication: None,
+            failed: false,
+        }
+    }
+
+    /// Removes the decode branch of the stream from the playback pipeline
+    fn close(&mut self) {
+        if let Some(decode) = self.decode.take() {
+            self.context.playback.remove_stream(decode);
+        }
+    }
+
+    /// Decodes the frame unless it is filtered out. With a `playout` time the
+    /// sink shows it at that time, otherwise right away. Returns whether the
+    /// frame is shown.
+    fn show(&mut self, video: &Video, playout: Option<Instant>) -> bool {
+        if self.failed || !self.filter.accepts(video) {
+            return false;
+        }
+
+        if self.decode.is_none() {
+            match self.context.playback.add_stream() {
+                Ok(decode) => {
+                    if self.context.playout_delay.is_some() {
+                        // The buffers are timestamped with their playout time instead
+                        decode.appsrc.set_property("do-timestamp", false);
+                    }
+                    println!("Showing user_id {}", video.user_id);
+                    self.decode = Some(decode);
+                }
+                Err(e) => {
+                    println!("Decoding user_id {} failed: {}", video.user_id, e);
+                    return false;
+                }
+            }
+        }
+        let decode = self.decode.as_mut().expect("decode branch was added");
+
+        if let Some(stream_info) = self.context.stream_infos.get(video.user_id) {
+            if self.caps.as_ref() != Some(&stream_info.caps) {
+                if let Err(e) = decode.apply_stream_info(&stream_info) {
+                    println!("{}", e);
+                    return false;
+                }
+                self.caps = Some(stream_info.caps);
+            }
+        }
+
+        if let Err(e) = decode.update_format(video.frame) {
+            println!("{}", e);
+            return false;
+        }
+        let pushed = match playout {
+            Some(playout) => push_frame_at(&decode.appsrc, video, playout),
+            None => push_frame(&decode.appsrc, video),
+        };
+        if let Err(e) = pushed {
+            println!("Decoding user_id {} stopped: {:?}", video.user_id, e);
+            self.failed = true;
+            return false;
+        }
+        let text = self.context.statistics.overlay_text();
+        self.context.playback.overlay.set_property("text", text);
+        true
+    }
+
+    /// Passes the frame through the jitter buffer of the stream and shows the
+    /// frames released by it. Recovered frames are due right away and only
+    /// counted as recovered once they are shown.
+    fn buffer(
+        &mut self,
+        video: &Video,
+        source_timestamp: Option<Duration>,
+        playout_delay: Duration,
+        recovered: bool,
+    ) {
+        let now = Instant::now();
+        let jitter_buffer = self
+            .jitter_buffer
+            .get_or_insert_with(|| JitterBuffer::new(playout_delay));
+        let insertion = if recovered {
+            jitter_buffer.insert_recovered(video.frame_num, video.temporal_layer, video.frame, now)
+        } else {
+            jitter_buffer.insert(
+                video.frame_num,
+                video.temporal_layer,
+                source_timestamp,
+                video.frame,
+                now,
+            )
+        };
+        match insertion {
+            Insertion::Buffered => (),
+            Insertion::Late => {
+                println!(
+                    "Frame {} of user_id {} arrived late",
+                    video.frame_num, video.user_id
+                );
+                self.context
+                    .statistics
+                    .update(video.user_id, StreamStatistics::record_late_frame);
+            }
+            Insertion::Discarded if recovered => {
+                println!(
+                    "Recovered frame {} of user_id {} too late, the following frames were shown",
+                    video.frame_num, video.user_id
+                );
+                self.context.statistics.update(video.user_id, |statistics| {
+                    statistics.record_unrecoverable_frames(1)
+                });
+            }
+            Insertion::Discarded => {
+                println!(
+                    "Discarded frame {} of user_id {}, it arrived after the following frames were shown",
+                    video.frame_num, video.user_id
+                );
+                self.context
+                    .statistics
+                    .update(video.user_id, StreamStatistics::record_discarded_frame);
+            }
+        }
+
+        self.release(now);
+    }
+
+    /// Time at which the jitter buffer releases its next frame
+    fn next_release(&self) -> Option<Instant> {
+        self.jitter_buffer.as_ref()?.next_playout()
+    }
+
+    /// Shows the frames the jitter buffer releases at `now`
+    fn release(&mut self, now: Instant) {
+        while let Some(buffered) = self
+            .jitter_buffer
+            .as_mut()
+            .and_then(|jitter_buffer| jitter_buffer.pop(now))
+        {
+            let released = Video {
+                user_id: self.user_id,
+                frame_num: buffered.frame_num,
+                temporal_layer: buffered.temporal_layer,
+                frame: &buffered.frame,
+            };
+            if self.show(&released, Some(buffered.playout)) && buffered.recovered {
+                println!(
+                    "Recovered frame {} of user_id {}",
+                    buffered.frame_num, self.user_id
+                );
+                self.context
+                    .statistics
+                    .update(self.user_id, StreamStatistics::record_recovered_frame);
+            }
+        }
+    }
+
+    /// Whether the playout delay leaves the time to recover the frames lost
+    /// out of parity groups of `group_size` frames. A group is checked once
+    /// the frame after it arrived, so a frame lost at the start of a group is
+    /// recovered `group_size` frame intervals later. Without the framerate of
+    /// the stream any playout delay is accepted.
+    fn delay_covers_group(&self, group_size: usize) -> bool {
+        let Some(playout_delay) = self.context.playout_delay else {
+            return false;
+        };
+        match self.context.stream_infos.get(self.user_id) {
+            Some(info) if info.framerate_num > 0 && info.framerate_den > 0 => {
+                let group_duration = Duration::from_secs_f64(
+                    group_size as f64 * info.framerate_den as f64 / info.framerate_num as f64,
+                );
+                playout_delay >= group_duration
+            }
+            _ => true,
+        }
+    }
+
+    /// Rebuilds the frames lost before `video` from the parity received so far.
+    /// Streams are only protected once their first parity arrived, and only
+    /// if the playout delay covers a parity group: otherwise the recovered
+    /// frames would arrive after the frames following them were shown.
+    fn recover_lost_frames(&mut self, video: &Video) {
+        let parities = self.context.parities.take(video.user_id);
+        if self.fec_decoder.is_none() && !self.fec_refused {
+            if let Some(parity) = parities.first() {
+                if self.delay_covers_group(parity.group_size()) {
+                    self.fec_decoder = Some(FecDecoder::default());
+                } else {
+                    println!(
+                        "Ignoring the parity of user_id {}, recovering lost frames requires a playout delay covering {} frames",
+                        video.user_id,
+                        parity.group_size()
+                    );
+                    self.fec_refused = true;
+                }
+            }
+        }
+        let (Some(fec_decoder), Some(playout_delay)) =
+            (&mut self.fec_decoder, self.context.playout_delay)
+        else {
+            return;
+        };
+        fec_decoder.receive(video.frame_num, video.frame);
+        for parity in parities {
+            fec_decoder.add_parity(parity);
+        }
+
+        for recovery in fec_decoder.recover(video.frame_num) {
+            match recovery {
+                Recovery::Recovered {
+                    frame_num,
+                    temporal_layer,
+                    frame,
+                } => {
+                    let recovered = Video {
+                        user_id: video.user_id,
+                        frame_num,
+                        temporal_layer,
+                        frame: &frame,
+                    };
+                    self.buffer(&recovered, None, playout_delay, true);
+                }
+                Recovery::Unrecoverable { lost_frames } => {
+                    println!(
+                        "{} lost frames of user_id {} could not be recovered",
+                        lost_frames, video.user_id
+                    );
+                    self.context.statistics.update(video.user_id, |statistics| {
+                        statistics.record_unrecoverable_frames(lost_frames)
+                    });
+                }
+            }
+        }
+    }
+
+    /// Whether the frame of `layer` belongs to the layer shown of the stream.
+    /// Switching layers starts the frame numbering, jitter buffer and
+    /// parity of the stream over.
+    fn select_layer(&mut self, video: &Video, layer: usize) -> bool {
+        let now = Instant::now();
+        let troubles = self
+            .context
+            .statistics
+            .update(video.user_id, |statistics| statistics.troubles());
+        let info = self.context.simulcast_infos.get(video.user_id);
+        self.layer_switch
+            .update(self.context.selection, info.as_ref(), troubles, now);
+        match self
+            .layer_switch
+            .decide(layer, h264::is_keyframe(video.frame))
+        {
+            LayerDecision::Show | LayerDecision::Switched { from: None } => true,
+            LayerDecision::Switched { from: Some(from) } => {
+                println!(
+                    "user_id {} switched from layer {} to layer {}",
+                    video.user_id, from, layer
+                );
+                self.jitter_buffer = None;
+                self.fec_decoder = None;
+                self.context
+                    .statistics
+                    .update(video.user_id, StreamStatistics::restart_numbering);
+                true
+            }
+            LayerDecision::Ignore => false,
+        }
+    }
+
+    /// Shows a received frame of `layer`
+    fn receive(&mut self, video: &Video, sample_info: &SampleInfo, layer: usize) {
+        if !self.select_layer(video, layer) {
+            return;
+        }
+
+        // The publisher taking over after a failover, or a restarted one,
+        // numbers its frames independently
+        let publication = sample_info.publication_handle;
+        if self
+            .publication
+            .replace(publication)
+            .is_some_and(|previous| previous != publication)
+        {
+            self.jitter_buffer = None;
+            self.fec_decoder = None;
+            self.context
+                .statistics
+                .update(video.user_id, StreamStatistics::restart_numbering);
+        }
+
+        // A stream joined mid-stream starts with the cached
+        // keyframe instead of waiting for the next one
+        let joined = !std::mem::replace(&mut self.started, true);
+        if joined && layer == 0 && !h264::is_keyframe(video.frame) {
+            if let Some(keyframe) = self.context.keyframes.get(video.user_id) {
+                if keyframe.frame_num.wrapping_sub(video.frame_num) < 0 {
+                    println!(
+                        "Starting user_id {} with cached keyframe {}",
+                        keyframe.user_id, keyframe.frame_num
+                    );
+                    let playout = self.context.playout_delay.map(|_| Instant::now());
+                    self.show(&keyframe.video(), playout);
+                }
+            }
+        }
+
+        self.context.statistics.update(video.user_id, |statistics| {
+            statistics.record(
+                video.frame_num,
+                video.frame.len(),
+                sample_info.source_timestamp,
+            )
+        });
+
+        // The parity protects the main layer only
+        if layer == 0 {
+            self.recover_lost_frames(video);
+        }
+
+        match self.context.playout_delay {
+            Some(playout_delay) => {
+                let source_timestamp = sample_info
+                    .source_timestamp
+                    .and_then(|t| Some(Duration::new(t.sec().try_into().ok()?, t.nanosec())));
+                self.buffer(video, source_timestamp, playout_delay, false);
+            }
+            None => self.show(video, None),
+        }
     }
 }
-impl From<&gstreamer::message::Error> for Error {
-    fn from(value: &gstreamer::message::Error) -> Self {
-        Self(format!("GStreamer state change error: {:?}", value))
+
+/// Deadline and liveliness of the streams, tracked by the DDS listeners
+struct Health {
+    alerts: Alerts,
+    statistics: Statistics,
+    /// `user_id` of every instance and publication seen, to attribute the
+    /// deadline and liveliness statuses to a stream
+    instances: HashMap<InstanceHandle, i16>,
+    publications: HashMap<InstanceHandle, i16>,
+    lost_publications: HashSet<InstanceHandle>,
+    /// Streams that missed their deadline since their last frame
+    stalled: HashSet<i16>,
+}
+
+impl Health {
+    /// Notes a received frame of `user_id`
+    fn observe(&mut self, user_id: i16, sample_info: &SampleInfo) {
+        self.instances.insert(sample_info.instance_handle, user_id);
+        self.publications
+            .insert(sample_info.publication_handle, user_id);
+        if self.stalled.remove(&user_id) {
+            let statistics = self
+                .statistics
+                .update(user_id, |statistics| statistics.clone());
+            self.alerts.raise(AlertKind::DeadlineRecovered, &statistics);
+        }
+    }
+
+    fn liveliness_changed(&mut self, status: LivelinessChangedStatus) {
+        println!(
+            "publisher liveliness changed: {} alive, {} not alive",
+            status.alive_count, status.not_alive_count
+        );
+
+        let publication = status.last_publication_handle;
+        let Some(&user_id) = self.publications.get(&publication) else {
+            return;
+        };
+        if status.not_alive_count_change > 0 {
+            self.lost_publications.insert(publication);
+            let statistics = self.statistics.update(user_id, |statistics| {
+                statistics.record_liveliness_lost();
+                statistics.clone()
+            });
+            self.alerts.raise(AlertKind::LivelinessLost, &statistics);
+        } else if status.alive_count_change > 0 && self.lost_publications.remove(&publication) {
+            let statistics = self
+                .statistics
+                .update(user_id, |statistics| statistics.clone());
+            self.alerts
+                .raise(AlertKind::LivelinessRestored, &statistics);
+        }
+    }
+
+    /// Counts every deadline period missed, but only alerts when the stream
+    /// stalls
+    fn requested_deadline_missed(&mut self, status: RequestedDeadlineMissedStatus) {
+        let Some(&user_id) = self.instances.get(&status.last_instance_handle) else {
+            return;
+        };
+        let statistics = self.statistics.update(user_id, |statistics| {
+            statistics.record_deadline_missed();
+            statistics.clone()
+        });
+        if self.stalled.insert(user_id) {
+            self.alerts.raise(AlertKind::DeadlineMissed, &statistics);
+        }
     }
 }
-impl From<DdsError> for Error {
-    fn from(value: DdsError) -> Self {
-        Self(format!("DDS error: {:?}", value))
+
+/// Frame taken from a reader, waiting in the queue of its stream
+struct ReceivedFrame {
+    user_id: i16,
+    frame_num: i32,
+    temporal_layer: u8,
+    frame: Vec<u8>,
+    sample_info: SampleInfo,
+    layer: usize,
+}
+
+/// Queues between the DDS listeners and the workers decoding the streams.
+/// Every stream gets its own bounded queue, worker thread and decode branch
+/// on its first frame, so that a slow decoder neither stalls the DDS listener
+/// thread nor the other streams.
+#[derive(Clone)]
+struct StreamQueues {
+    context: Arc<WorkerContext>,
+    health: Arc<Mutex<Health>>,
+    default: StreamQueueConfig,
+    per_stream: BTreeMap<String, StreamQueueConfig>,
+    senders: Arc<Mutex<HashMap<i16, (SyncSender<ReceivedFrame>, Overflow)>>>,
+}
+
+impl StreamQueues {
+    fn config(&self, user_id: i16) -> &StreamQueueConfig {
+        self.per_stream
+            .get(&user_id.to_string())
+            .unwrap_or(&self.default)
+    }
+
+    /// Hands the frame to the worker of its stream. When the queue is full
+    /// the frame is dropped or the caller waits, as configured for the stream
+    fn enqueue(&self, frame: ReceivedFrame) {
+        let user_id = frame.user_id;
+        self.health
+            .lock()
+            .expect("health lock not poisoned")
+            .observe(user_id, &frame.sample_info);
+        let (sender, overflow) = self
+            .senders
+            .lock()
+            .expect("stream queues lock not poisoned")
+            .entry(user_id)
+            .or_insert_with(|| {
+                let config = self.config(user_id);
+                (self.spawn_worker(user_id, config.capacity), config.overflow)
+            })
+            .clone();
+
+        let running = match overflow {
+            Overflow::Block => sender.send(frame).is_ok(),
+            Overflow::Drop => match sender.try_send(frame) {
+                Ok(()) => true,
+                Err(TrySendError::Full(frame)) => {
+                    println!(
+                        "Queue of user_id {} full, dropped frame {}",
+                        user_id, frame.frame_num
+                    );
+                    self.context
+                        .statistics
+                        .update(user_id, StreamStatistics::record_overflow_frame);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            },
+        };
+        if !running {
+            // The next frame starts a new worker
+            println!("Worker of user_id {} stopped, dropped its frame", user_id);
+            self.senders
+                .lock()
+                .expect("stream queues lock not poisoned")
+                .remove(&user_id);
+        }
+    }
+
+    fn spawn_worker(&self, user_id: i16, capacity: usize) -> SyncSender<ReceivedFrame> {
+        let (sender, receiver) = mpsc::sync_channel::<ReceivedFrame>(capacity.max(1));
+        let mut worker = StreamWorker::new(user_id, self.context.clone());
+        std::thread::spawn(move || loop {
+            if worker.failed {
+                // The next frame of the stream starts a new worker
+                worker.close();
+                break;
+            }
+            // Frames held by the jitter buffer are released when due, even
+            // if no frame arrives in the meantime
+            let received = match worker.next_release() {
+                Some(release) => {
+                    match receiver.recv_timeout(release.saturating_duration_since(Instant::now())) {
+                        Ok(received) => received,
+                        Err(RecvTimeoutError::Timeout) => {
+                            worker.release(Instant::now());
+                            continue;
+                        }
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match receiver.recv() {
+                    Ok(received) => received,
+                    Err(_) => break,
+                },
+            };
+            let video = Video {
+                user_id: received.user_id,
+                frame_num: received.frame_num,
+                temporal_layer: received.temporal_layer,
+                frame: &received.frame,
+            };
+            worker.receive(&video, &received.sample_info, received.layer);
+
+            use std::io::{self, Write};
+            io::stdout().flush().ok();
+        });
+        sender
+    }
+
+    fn liveliness_changed(&self, status: LivelinessChangedStatus) {
+        self.health
+            .lock()
+            .expect("health lock not poisoned")
+            .liveliness_changed(status);
+    }
+
+    fn requested_deadline_missed(&self, status: RequestedDeadlineMissedStatus) {
+        self.health
+            .lock()
+            .expect("health lock not poisoned")
+            .requested_deadline_missed(status);
     }
 }
 
-struct Listener {
-    appsrc: gstreamer_app::AppSrc,
+/// Receives the frames of `layer` sent as one sample
+struct VideoListener {
+    ownership_monitor: Option<OwnershipMonitor>,
+    queues: StreamQueues,
+    layer: usize,
 }
 
-impl<'a> DataReaderListener<'a> for Listener {
+impl<'a> DataReaderListener<'a> for VideoListener {
     type Foo = Video<'a>;
 
-    fn on_data_available(
-        &mut self,
-        the_reader: dust_dds::subscription::data_reader::DataReader<Self::Foo>,
-    ) {
-        if let Ok(samples) =
-            the_reader.read(1, ANY_SAMPLE_STATE, ANY_VIEW_STATE, ANY_INSTANCE_STATE)
-        {
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
             for sample in samples {
                 if let Ok(sample_data) = sample.data() {
                     println!("sample received: {:?}", sample_data.frame_num);
+                    let sample_info = sample.sample_info();
 
-                    let mut buffer = gstreamer::Buffer::with_size(sample_data.frame.len())
-                        .expect("buffer creation failed");
-                    {
-                        let buffer_ref = buffer.get_mut().expect("mutable buffer");
-                        let mut buffer_samples =
-                            buffer_ref.map_writable().expect("writeable buffer");
-                        buffer_samples.clone_from_slice(sample_data.frame);
+                    if let Some(ownership_monitor) = &mut self.ownership_monitor {
+                        let publication_handle = sample_info.publication_handle;
+                        if let Some(previous) = ownership_monitor
+                            .update(sample_info.instance_handle, publication_handle)
+                        {
+                            let strength = the_reader
+                                .get_matched_publication_data(publication_handle)
+                                .map(|data| data.ownership_strength().value);
+                            println!(
+                                "user_id {} switched from publisher {:?} to publisher {:?} (ownership strength {:?})",
+                                sample_data.user_id, previous, publication_handle, strength
+                            );
+                        }
                     }
-                    self.appsrc
-                        .push_buffer(buffer)
-                        .expect("push buffer into appsrc to succeed");
 
-                    use std::io::{self, Write};
-                    io::stdout().flush().ok();
+                    self.queues.enqueue(ReceivedFrame {
+                        user_id: sample_data.user_id,
+                        frame_num: sample_data.frame_num,
+                        temporal_layer: sample_data.temporal_layer,
+                        frame: sample_data.frame.to_vec(),
+                        sample_info,
+                        layer: self.layer,
+                    });
                 }
             }
         }
     }
+
+    fn on_liveliness_changed(
+        &mut self,
+        _the_reader: DataReader<Self::Foo>,
+        status: LivelinessChangedStatus,
+    ) {
+        self.queues.liveliness_changed(status);
+    }
+
+    fn on_requested_deadline_missed(
+        &mut self,
+        _the_reader: DataReader<Self::Foo>,
+        status: RequestedDeadlineMissedStatus,
+    ) {
+        self.queues.requested_deadline_missed(status);
+    }
+}
+
+/// Gives up the frames whose fragments did not all arrive in time
+fn expire_fragments(reassembler: &Mutex<Reassembler>, statistics: &Statistics) {
+    let expired = reassembler
+        .lock()
+        .expect("reassembler lock not poisoned")
+        .expire(Instant::now());
+    for incomplete in expired {
+        println!(
+            "Gave up frame {} of user_id {}, {} of {} fragments missing",
+            incomplete.frame_num,
+            incomplete.user_id,
+            incomplete.missing_fragments,
+            incomplete.fragment_count
+        );
+        statistics.update(
+            incomplete.user_id,
+            StreamStatistics::record_incomplete_frame,
+        );
+    }
+}
+
+/// Receives the frames sent as fragments and reassembles them
+struct FragmentListener {
+    queues: StreamQueues,
+    reassembler: Arc<Mutex<Reassembler>>,
+}
+
+impl<'a> DataReaderListener<'a> for FragmentListener {
+    type Foo = VideoFragment;
+
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
+            let now = Instant::now();
+            for sample in samples {
+                if let Ok(fragment) = sample.data() {
+                    let reassembled = self
+                        .reassembler
+                        .lock()
+                        .expect("reassembler lock not poisoned")
+                        .insert(fragment, now);
+                    if let Some(reassembled) = reassembled {
+                        println!("sample received: {:?}", reassembled.frame_num);
+                        self.queues.enqueue(ReceivedFrame {
+                            user_id: reassembled.user_id,
+                            frame_num: reassembled.frame_num,
+                            temporal_layer: reassembled.temporal_layer,
+                            frame: reassembled.frame,
+                            sample_info: sample.sample_info(),
+                            layer: 0,
+                        });
+                    }
+                }
+            }
+
+            expire_fragments(&self.reassembler, &self.queues.context.statistics);
+
+            use std::io::{self, Write};
+            io::stdout().flush().ok();
+        }
+    }
+
+    fn on_liveliness_changed(
+        &mut self,
+        _the_reader: DataReader<Self::Foo>,
+        status: LivelinessChangedStatus,
+    ) {
+        self.queues.liveliness_changed(status);
+    }
+
+    fn on_requested_deadline_missed(
+        &mut self,
+        _the_reader: DataReader<Self::Foo>,
+        status: RequestedDeadlineMissedStatus,
+    ) {
+        self.queues.requested_deadline_missed(status);
+    }
+}
+
+/// Parses the words `<user_id> <command>` entered after `ptz`
+fn parse_ptz(words: &[&str]) -> Result<(i16, PtzCommand), Error> {
+    let user_id = words
+        .first()
+        .and_then(|word| word.parse().ok())
+        .ok_or_else(|| Error("Expected `ptz <user_id> <command>`".to_string()))?;
+    Ok((user_id, PtzCommand::parse(&words[1..])?))
+}
+
+/// Parses the words `<user_id> <command> [arguments]` entered after `control`
+fn parse_control(words: &[&str]) -> Result<(i16, ControlCommand), Error> {
+    let mut words = words.iter();
+    let user_id = words
+        .next()
+        .and_then(|word| word.parse().ok())
+        .ok_or_else(|| Error("Expected `control <user_id> <command>`".to_string()))?;
+    let name = words.next().copied().unwrap_or_default();
+    let arguments = words
+        .map(|word| word.parse())
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|e| Error(format!("Invalid argument: {}", e)))?;
+    Ok((user_id, ControlCommand::new(name, &arguments)?))
 }
 
 fn main() -> Result<(), Error> {
+    let args = Args::parse();
+    let mut config = Config::load(&args.config)?;
+    if let Some(exclusive_ownership) = args.exclusive_ownership {
+        config.qos.exclusive_ownership = exclusive_ownership;
+    }
+    if !args.user_ids.is_empty() {
+        config.subscriber.user_ids = args.user_ids;
+    }
+    if let Some(keyframes_only) = args.keyframes_only {
+        config.subscriber.keyframes_only = keyframes_only;
+    }
+    if args.minimum_separation_ms.is_some() {
+        config.subscriber.minimum_separation_ms = args.minimum_separation_ms;
+    }
+    if let Some(stats_overlay) = args.stats_overlay {
+        config.subscriber.stats_overlay = stats_overlay;
+    }
+    if args.playout_delay_ms.is_some() {
+        config.subscriber.playout_delay_ms = args.playout_delay_ms;
+    }
+    if args.alert_command.is_some() {
+        config.subscriber.alert_command = args.alert_command;
+    }
+    if let Some(capacity) = args.stream_queue_capacity {
+        config.subscriber.stream_queue.capacity = capacity;
+    }
+    if let Some(layer) = args.layer {
+        config.subscriber.layer = layer;
+    }
+    if let Some(auto_layer) = args.auto_layer {
+        config.subscriber.auto_layer = auto_layer;
+    }
+    if args.viewport_height.is_some() {
+        config.subscriber.viewport_height = args.viewport_height;
+    }
+    if args.max_temporal_layer.is_some() {
+        config.subscriber.max_temporal_layer = args.max_temporal_layer;
+    }
+    if let Some(annotations) = args.annotations {
+        config.subscriber.annotations = annotations;
+    }
+    if !args.hidden_annotation_producers.is_empty() {
+        config.subscriber.hidden_annotation_producers = args.hidden_annotation_producers;
+    }
+    if !args.processors.is_empty() {
+        config.subscriber.processors = args.processors;
+    }
+    if args.config.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
     gstreamer::init()?;
 
-    let domain_id = 0;
     let participant_factory = DomainParticipantFactory::get_instance();
-    let participant =
-        participant_factory.create_participant(domain_id, QosKind::Default, None, NO_STATUS)?;
+    let participant = participant_factory.create_participant(
+        config.domain_id,
+        QosKind::Specific(participant_qos()),
+        None,
+        NO_STATUS,
+    )?;
     let topic = participant.create_topic::<Video>(
-        "VideoStream",
-        "Video",
+        &config.video_topic,
+        VIDEO_TYPE_NAME,
         QosKind::Default,
         None,
         NO_STATUS,
     )?;
-    let subscriber = participant.create_subscriber(QosKind::Default, None, NO_STATUS)?;
-
-    let pipeline = gstreamer::parse::launch(
-        r#"appsrc name=appsrc ! openh264dec ! videoconvert ! taginject tags="title=Subscriber" ! autovideosink"#,
+    let subscriber = participant.create_subscriber(
+        QosKind::Specific(subscriber_qos(&config.qos.rooms)),
+        None,
+        NO_STATUS,
     )?;
 
+    let annotations = AnnotationCache::default();
+    let annotation_overlay = AnnotationOverlay::new(
+        annotations.clone(),
+        config.subscriber.hidden_annotation_producers,
+    );
+    let playback = PlaybackPipelineBuilder::new("Subscriber")
+        .sink(&config.subscriber.sink)
+        .decoders(config.codec.decoders)
+        .annotations(
+            config
+                .subscriber
+                .annotations
+                .then(|| annotation_overlay.clone()),
+        )
+        .build()?;
+    let processors = config
+        .subscriber
+        .processors
+        .iter()
+        .map(|name| {
+            let processor = create_processor(name)?;
+            let frame_sink = playback.add_frame_sink(processor.format())?;
+            Ok((processor, frame_sink))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let playout_delay = config
+        .subscriber
+        .playout_delay_ms
+        .map(Duration::from_millis);
+    let playback = Arc::new(playback);
+    let pipeline = playback.pipeline.clone();
+    let overlay = playback.overlay.clone();
+    overlay.set_property("silent", !config.subscriber.stats_overlay);
+
     pipeline.set_state(gstreamer::State::Playing)?;
 
-    let bin = pipeline.downcast_ref::<gstreamer::Bin>().expect("Pipeline is bin");
-    let appsrc_element = bin.by_name("appsrc").expect("Pipeline has appsrc");
-    let appsrc = appsrc_element.downcast::<gstreamer_app::AppSrc>().expect("is AppSrc type");
-    let src_caps = gstreamer::Caps::builder("video/x-h264")
-        .field("stream-format", "byte-stream")
-        .field("alignment", "au")
-        .field("profile", "constrained-baseline")
-        .build();
-    appsrc.set_caps(Some(&src_caps));
+    let stream_info_topic = participant.create_topic::<StreamInfo>(
+        STREAM_INFO_TOPIC_NAME,
+        STREAM_INFO_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let stream_infos = StreamInfoCache::default();
+    let _stream_info_reader = subscriber.create_datareader(
+        &stream_info_topic,
+        QosKind::Specific(stream_info_reader_qos(config.qos.exclusive_ownership)),
+        Some(Box::new(StreamInfoListener {
+            cache: stream_infos.clone(),
+        })),
+        &[StatusKind::DataAvailable],
+    )?;
+
+    let keyframe_topic = participant.create_topic::<Video>(
+        &keyframe_topic_name(&config.video_topic),
+        VIDEO_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let keyframes = KeyframeCache::default();
+    let _keyframe_reader = subscriber.create_datareader(
+        &keyframe_topic,
+        QosKind::Specific(keyframe_reader_qos(config.qos.exclusive_ownership)),
+        Some(Box::new(KeyframeListener {
+            cache: keyframes.clone(),
+        })),
+        &[StatusKind::DataAvailable],
+    )?;
 
+    let parity_topic = participant.create_topic::<VideoParity>(
+        &parity_topic_name(&config.video_topic),
+        PARITY_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let parities = ParityCache::default();
+    let _parity_reader = subscriber.create_datareader(
+        &parity_topic,
+        QosKind::Specific(parity_reader_qos(config.qos.exclusive_ownership)),
+        Some(Box::new(ParityListener {
+            cache: parities.clone(),
+        })),
+        &[StatusKind::DataAvailable],
+    )?;
+
+    let simulcast_info_topic = participant.create_topic::<SimulcastInfo>(
+        SIMULCAST_INFO_TOPIC_NAME,
+        SIMULCAST_INFO_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let simulcast_infos = SimulcastInfoCache::default();
+    let _simulcast_info_reader = subscriber.create_datareader(
+        &simulcast_info_topic,
+        QosKind::Specific(simulcast_info_reader_qos()),
+        Some(Box::new(SimulcastInfoListener {
+            cache: simulcast_infos.clone(),
+        })),
+        &[StatusKind::DataAvailable],
+    )?;
+
+    let annotation_topic = participant.create_topic::<Annotation>(
+        ANNOTATION_TOPIC_NAME,
+        ANNOTATION_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let _annotation_reader = if config.subscriber.annotations {
+        Some(subscriber.create_datareader(
+            &annotation_topic,
+            QosKind::Default,
+            Some(Box::new(AnnotationListener { cache: annotations })),
+            &[StatusKind::DataAvailable],
+        )?)
+    } else {
+        None
+    };
+
+    if !processors.is_empty() {
+        let processor_publisher = participant.create_publisher(
+            QosKind::Specific(publisher_qos(&config.qos.rooms)),
+            None,
+            NO_STATUS,
+        )?;
+        let event_topic = participant.create_topic::<ProcessorEvent>(
+            PROCESSOR_EVENT_TOPIC_NAME,
+            PROCESSOR_EVENT_TYPE_NAME,
+            QosKind::Default,
+            None,
+            NO_STATUS,
+        )?;
+        for (processor, frame_sink) in processors {
+            let output = ProcessorOutput::new(
+                processor.name(),
+                Some(processor_publisher.create_datawriter(
+                    &annotation_topic,
+                    QosKind::Default,
+                    None,
+                    NO_STATUS,
+                )?),
+                Some(processor_publisher.create_datawriter(
+                    &event_topic,
+                    QosKind::Default,
+                    None,
+                    NO_STATUS,
+                )?),
+            );
+            println!("Processing the frames with {}", processor.name());
+            spawn_processor(frame_sink, processor, output);
+        }
+    }
+
+    let publisher = participant.create_publisher(
+        QosKind::Specific(publisher_qos(&config.qos.rooms)),
+        None,
+        NO_STATUS,
+    )?;
+    let alert_topic = participant.create_topic::<StreamAlert>(
+        STREAM_ALERT_TOPIC_NAME,
+        STREAM_ALERT_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let alert_writer =
+        publisher.create_datawriter(&alert_topic, QosKind::Default, None, NO_STATUS)?;
+    let alerts = Alerts::new(alert_writer, config.subscriber.alert_command);
+
+    let control_topic = participant.create_topic::<CameraControl>(
+        CAMERA_CONTROL_TOPIC_NAME,
+        CAMERA_CONTROL_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let control_publisher = participant.create_publisher(
+        QosKind::Specific(publisher_qos(&config.qos.rooms)),
+        None,
+        NO_STATUS,
+    )?;
+    let control_writer = control_publisher.create_datawriter(
+        &control_topic,
+        QosKind::Specific(control_writer_qos()),
+        None,
+        NO_STATUS,
+    )?;
+    let camera_state_topic = participant.create_topic::<CameraState>(
+        CAMERA_STATE_TOPIC_NAME,
+        CAMERA_STATE_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let _camera_state_reader = subscriber.create_datareader(
+        &camera_state_topic,
+        QosKind::Specific(camera_state_reader_qos()),
+        Some(Box::new(CameraStateListener)),
+        &[StatusKind::DataAvailable],
+    )?;
+
+    let ptz_topic = participant.create_topic::<PtzControl>(
+        PTZ_CONTROL_TOPIC_NAME,
+        PTZ_CONTROL_TYPE_NAME,
+        QosKind::Default,
+        None,
+        NO_STATUS,
+    )?;
+    let ptz = PtzSender::new(control_publisher.create_datawriter(
+        &ptz_topic,
+        QosKind::Specific(control_writer_qos()),
+        None,
+        NO_STATUS,
+    )?);
+    // Navigating in the window moves the view of the only stream shown
+    if let [user_id] = config.subscriber.user_ids[..] {
+        let navigation_ptz = ptz.clone();
+        connect_navigation(&overlay, move |event, size| {
+            if let Some(command) = PtzCommand::from_navigation(event, size) {
+                navigation_ptz.send(user_id, &command);
+            }
+        });
+    }
+
+    let stats_overlay = overlay.clone();
+    std::thread::spawn(move || {
+        let mut sequence = 0;
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            let words: Vec<_> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["stats"] => {
+                    let silent = stats_overlay.property::<bool>("silent");
+                    stats_overlay.set_property("silent", !silent);
+                }
+                ["annotations"] => {
+                    for (producer, shown) in annotation_overlay.producers() {
+                        println!(
+                            "Annotations of {}: {}",
+                            producer,
+                            if shown { "shown" } else { "hidden" }
+                        );
+                    }
+                }
+                ["annotations", producer] => {
+                    let shown = annotation_overlay.toggle(producer);
+                    println!(
+                        "Annotations of {} {}",
+                        producer,
+                        if shown { "shown" } else { "hidden" }
+                    );
+                }
+                ["ptz", request @ ..] => match parse_ptz(request) {
+                    Ok((user_id, command)) => ptz.send(user_id, &command),
+                    Err(Error(message)) => println!("{}", message),
+                },
+                ["control", request @ ..] => match parse_control(request) {
+                    Ok((user_id, command)) => {
+                        sequence += 1;
+                        let control = CameraControl::new(user_id, sequence, command);
+                        match control_writer.write(&control, None) {
+                            Ok(()) => println!("Sent request {} to user_id {}", sequence, user_id),
+                            Err(e) => println!("Sending request failed: {:?}", e),
+                        }
+                    }
+                    Err(Error(message)) => println!("{}", message),
+                },
+                _ => println!(
+                    "Unknown command {:?}, expected `stats`, `annotations [producer]`, `control <user_id> <command>` or `ptz <user_id> <command>`",
+                    line.trim()
+                ),
+            }
+        }
+    });
+
+    let filter = VideoFilter::new(
+        config.subscriber.user_ids,
+        config.subscriber.keyframes_only,
+        config
+            .subscriber
+            .minimum_separation_ms
+            .map(Duration::from_millis),
+        config.subscriber.max_temporal_layer,
+    );
+    let exclusive_ownership = config.qos.exclusive_ownership;
+    let selection = if config.subscriber.auto_layer {
+        LayerSelection::Auto {
+            viewport_height: config.subscriber.viewport_height,
+        }
+    } else {
+        LayerSelection::Fixed(config.subscriber.layer)
+    };
+    let statistics = Statistics::default();
+    let context = Arc::new(WorkerContext {
+        playback,
+        filter,
+        statistics: statistics.clone(),
+        stream_infos,
+        keyframes,
+        playout_delay,
+        parities,
+        selection,
+        simulcast_infos,
+    });
+    let health = Arc::new(Mutex::new(Health {
+        alerts,
+        statistics,
+        instances: HashMap::new(),
+        publications: HashMap::new(),
+        lost_publications: HashSet::new(),
+        stalled: HashSet::new(),
+    }));
+    let queues = StreamQueues {
+        context,
+        health,
+        default: config.subscriber.stream_queue,
+        per_stream: config.subscriber.stream_queues,
+        senders: Arc::new(Mutex::new(HashMap::new())),
+    };
     let _reader = subscriber.create_datareader(
         &topic,
+        QosKind::Specific(video_reader_qos(exclusive_ownership, config.qos.framerate)),
+        Some(Box::new(VideoListener {
+            ownership_monitor: exclusive_ownership.then(OwnershipMonitor::default),
+            queues: queues.clone(),
+            layer: 0,
+        })),
+        &[
+            StatusKind::DataAvailable,
+            StatusKind::LivelinessChanged,
+            StatusKind::RequestedDeadlineMissed,
+        ],
+    )?;
+
+    // The smaller layers are only received when they may be shown
+    let layer_count = match selection {
+        LayerSelection::Fixed(layer) => (layer + 1).min(MAX_LAYERS),
+        LayerSelection::Auto { .. } => MAX_LAYERS,
+    };
+    let mut layer_readers = Vec::new();
+    for layer in 1..layer_count {
+        let layer_topic = participant.create_topic::<Video>(
+            &layer_topic_name(&config.video_topic, layer),
+            VIDEO_TYPE_NAME,
+            QosKind::Default,
+            None,
+            NO_STATUS,
+        )?;
+        layer_readers.push(subscriber.create_datareader(
+            &layer_topic,
+            QosKind::Specific(video_reader_qos(exclusive_ownership, config.qos.framerate)),
+            Some(Box::new(VideoListener {
+                ownership_monitor: exclusive_ownership.then(OwnershipMonitor::default),
+                queues: queues.clone(),
+                layer,
+            })),
+            &[StatusKind::DataAvailable],
+        )?);
+    }
+
+    let fragment_topic = participant.create_topic::<VideoFragment>(
+        &fragment_topic_name(&config.video_topic),
+        FRAGMENT_TYPE_NAME,
         QosKind::Default,
-        Some(Box::new(Listener { appsrc })),
-        &[StatusKind::DataAvailable],
+        None,
+        NO_STATUS,
+    )?;
+    let fragment_timeout = Duration::from_millis(config.subscriber.fragment_timeout_ms);
+    let reassembler = Arc::new(Mutex::new(Reassembler::new(fragment_timeout)));
+    // The last fragments of a stream that stopped are not followed by others
+    // expiring them
+    let expiry_reassembler = reassembler.clone();
+    let expiry_statistics = queues.context.statistics.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep((fragment_timeout / 2).max(Duration::from_millis(10)));
+        expire_fragments(&expiry_reassembler, &expiry_statistics);
+    });
+    let _fragment_reader = subscriber.create_datareader(
+        &fragment_topic,
+        QosKind::Specific(fragment_reader_qos(
+            exclusive_ownership,
+            config.qos.framerate,
+        )),
+        Some(Box::new(FragmentListener {
+            queues,
+            reassembler,
+        })),
+        &[
+            StatusKind::DataAvailable,
+            StatusKind::LivelinessChanged,
+            StatusKind::RequestedDeadlineMissed,
+        ],
     )?;
 
     // Wait until error or EOS
@@ -0,0 +1,24 @@
+use dust_dds::infrastructure::instance::InstanceHandle;
+use std::collections::HashMap;
+
+/// Keeps track of the writer currently owning each stream instance so that a
+/// change of owner, e.g. a failover to the backup camera, can be reported.
+#[derive(Default)]
+pub struct OwnershipMonitor {
+    owners: HashMap<InstanceHandle, InstanceHandle>,
+}
+
+impl OwnershipMonitor {
+    /// Records that a sample of `instance` was written by `publication`.
+    /// Returns the previous owner if the ownership changed.
+    pub fn update(
+        &mut self,
+        instance: InstanceHandle,
+        publication: InstanceHandle,
+    ) -> Option<InstanceHandle> {
+        match self.owners.insert(instance, publication) {
+            Some(previous) if previous != publication => Some(previous),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+
+/// Amount of data that may be sent at once after an idle period
+const BURST: Duration = Duration::from_millis(20);
+
+/// Token bucket limiting the bandwidth of a publisher.
+///
+/// Writes are deferred until the bucket holds tokens for their size, which
+/// spreads the fragments of large keyframes over the following frame
+/// intervals. While the bucket is in debt, non-reference frames are dropped
+/// instead, as the following frames do not depend on them.
+pub struct Pacer {
+    /// Bytes per second
+    rate: f64,
+    capacity: f64,
+    /// Bytes that may be sent right away, negative while in debt
+    tokens: f64,
+    last_refill: Instant,
+    deferred_samples: u64,
+    total_delay: Duration,
+    dropped_frames: u64,
+}
+
+/// Size in bytes of the largest sample written at once under `max_bitrate`
+pub fn burst_size(max_bitrate: u32) -> usize {
+    (f64::from(max_bitrate.max(1)) / 8.0 * BURST.as_secs_f64()).max(1.0) as usize
+}
+
+impl Pacer {
+    /// `max_bitrate` in bit/s
+    pub fn new(max_bitrate: u32) -> Self {
+        let rate = f64::from(max_bitrate.max(1)) / 8.0;
+        let capacity = burst_size(max_bitrate) as f64;
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+            deferred_samples: 0,
+            total_delay: Duration::ZERO,
+            dropped_frames: 0,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Decides whether a frame is sent at all. Non-reference frames are
+    /// dropped while over budget.
+    pub fn admit(&mut self, is_reference: bool, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < 0.0 && !is_reference {
+            self.dropped_frames += 1;
+            return false;
+        }
+        true
+    }
+
+    /// Takes the tokens for a sample of `size` bytes and returns how long to
+    /// wait before writing it, which is until the bucket holds them. Samples
+    /// larger than the bucket wait until it is full and leave it in debt.
+    pub fn reserve(&mut self, size: usize, now: Instant) -> Duration {
+        self.refill(now);
+        let needed = (size as f64).min(self.capacity);
+        let wait = if self.tokens < needed {
+            Duration::from_secs_f64((needed - self.tokens) / self.rate)
+        } else {
+            Duration::ZERO
+        };
+        self.tokens -= size as f64;
+        if !wait.is_zero() {
+            self.deferred_samples += 1;
+            self.total_delay += wait;
+        }
+        wait
+    }
+
+    /// Current state, as shown by the `stats` command
+    pub fn summary(&self) -> String {
+        format!(
+            "limit: {:.0} kbit/s\navailable: {:.0} bytes\ndeferred: {} samples, {} ms in total\ndropped: {} non-reference frames",
+            self.rate * 8.0 / 1000.0,
+            self.tokens,
+            self.deferred_samples,
+            self.total_delay.as_millis(),
+            self.dropped_frames
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1000 bytes per second with a bucket of 20 bytes
+    const MAX_BITRATE: u32 = 8000;
+
+    fn millis(wait: Duration) -> u64 {
+        (wait.as_secs_f64() * 1000.0).round() as u64
+    }
+
+    #[test]
+    fn samples_within_the_burst_are_written_at_once() {
+        let now = Instant::now();
+        let mut pacer = Pacer::new(MAX_BITRATE);
+        assert_eq!(pacer.reserve(10, now), Duration::ZERO);
+        assert_eq!(pacer.reserve(10, now), Duration::ZERO);
+        assert_eq!(millis(pacer.reserve(10, now)), 10);
+    }
+
+    #[test]
+    fn samples_wait_until_the_bucket_holds_them() {
+        let now = Instant::now();
+        let mut pacer = Pacer::new(MAX_BITRATE);
+        assert_eq!(pacer.reserve(15, now), Duration::ZERO);
+        assert_eq!(millis(pacer.reserve(15, now)), 10);
+        // The bucket refilled while the sample waited
+        let later = now + Duration::from_millis(10);
+        assert_eq!(millis(pacer.reserve(15, later)), 15);
+    }
+
+    #[test]
+    fn samples_larger_than_the_bucket_wait_until_it_is_full() {
+        let now = Instant::now();
+        let mut pacer = Pacer::new(MAX_BITRATE);
+        assert_eq!(pacer.reserve(10, now), Duration::ZERO);
+        assert_eq!(millis(pacer.reserve(100, now)), 10);
+        // The debt is paid off before the next sample
+        let later = now + Duration::from_millis(10);
+        assert_eq!(millis(pacer.reserve(10, later)), 90);
+    }
+
+    #[test]
+    fn non_reference_frames_are_dropped_in_debt() {
+        let now = Instant::now();
+        let mut pacer = Pacer::new(MAX_BITRATE);
+        assert!(pacer.admit(false, now));
+        pacer.reserve(100, now);
+        assert!(!pacer.admit(false, now));
+        assert!(pacer.admit(true, now));
+        assert!(pacer.admit(false, now + Duration::from_millis(80)));
+        assert_eq!(pacer.dropped_frames, 1);
+    }
+
+    #[test]
+    fn burst_size_follows_the_bitrate() {
+        assert_eq!(burst_size(MAX_BITRATE), 20);
+        assert_eq!(burst_size(1_000_000), 2500);
+        assert_eq!(burst_size(0), 1);
+    }
+}
@@ -0,0 +1,1239 @@
+use crate::{
+    annotation::AnnotationOverlay,
+    error::Error,
+    h264::{self, SequenceParameterSet},
+    pacing::Pacer,
+    processor::RawFormat,
+    ptz::PtzPosition,
+    sender::{FrameWriters, QueuedFrame, SendQueue},
+    simulcast::SimulcastLayer,
+    stream_info::StreamInfo,
+    video::Video,
+};
+use gstreamer::prelude::*;
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// H.264 encoders in order of preference
+pub const H264_ENCODERS: &[&str] = &["openh264enc", "x264enc", "avenc_h264"];
+/// H.264 encoders able to produce temporal layers
+pub const TEMPORAL_LAYER_ENCODERS: &[&str] = &["x264enc"];
+pub const MAX_TEMPORAL_LAYERS: u8 = 3;
+/// H.264 decoders in order of preference
+pub const H264_DECODERS: &[&str] = &["openh264dec", "avdec_h264"];
+
+const DEFAULT_BITRATE: u32 = 1_280_000;
+/// Decoded frames waiting for a frame processor before frames are dropped
+const FRAME_SINK_CAPACITY: u32 = 2;
+
+/// Caps of the frames transported in the `Video` samples. The exact caps of
+/// a stream are announced by its `StreamInfo`.
+pub fn h264_caps() -> gstreamer::Caps {
+    gstreamer::Caps::builder("video/x-h264")
+        .field("stream-format", "byte-stream")
+        .field("alignment", "au")
+        .build()
+}
+
+/// Caps forced on the encoder output so that all decoders can handle the stream
+/// Temporal layers are built from B frames, which the baseline profile lacks
+fn encoder_caps(temporal_layers: u8) -> gstreamer::Caps {
+    let profile = if temporal_layers > 1 {
+        "main"
+    } else {
+        "constrained-baseline"
+    };
+    gstreamer::Caps::builder("video/x-h264")
+        .field("stream-format", "byte-stream")
+        .field("alignment", "au")
+        .field("profile", profile)
+        .build()
+}
+
+fn camera_caps() -> gstreamer::Caps {
+    gstreamer::Caps::builder("video/x-raw")
+        .field(
+            "framerate",
+            gstreamer::FractionRange::new(
+                gstreamer::Fraction::new(1, 1),
+                gstreamer::Fraction::new(25, 1),
+            ),
+        )
+        .field("width", gstreamer::IntRange::new(1, 1280))
+        .field("height", gstreamer::IntRange::new(1, 720))
+        .build()
+}
+
+/// Fails with the names of all `elements` missing in the plugin registry
+fn check_elements(elements: &[&str]) -> Result<(), Error> {
+    let missing: Vec<_> = elements
+        .iter()
+        .filter(|name| gstreamer::ElementFactory::find(name).is_none())
+        .copied()
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(Error(format!(
+            "GStreamer elements not available, install the plugins providing: {}",
+            missing.join(", ")
+        )))
+    }
+}
+
+/// Returns the first element of the `preference` list found in the plugin registry
+fn select_element(kind: &str, preference: &[String]) -> Result<String, Error> {
+    preference
+        .iter()
+        .find(|name| gstreamer::ElementFactory::find(name).is_some())
+        .cloned()
+        .ok_or_else(|| {
+            Error(format!(
+                "No {} available, tried: {}",
+                kind,
+                preference.join(", ")
+            ))
+        })
+}
+
+fn make(factory: &str) -> Result<gstreamer::Element, Error> {
+    Ok(gstreamer::ElementFactory::make(factory).build()?)
+}
+
+fn make_leaky_queue() -> Result<gstreamer::Element, Error> {
+    let queue = make("queue")?;
+    queue.set_property_from_str("leaky", "downstream");
+    Ok(queue)
+}
+
+fn make_capsfilter(caps: gstreamer::Caps) -> Result<gstreamer::Element, Error> {
+    Ok(gstreamer::ElementFactory::make("capsfilter")
+        .property("caps", caps)
+        .build()?)
+}
+
+fn factory_name(element: &gstreamer::Element) -> String {
+    element
+        .factory()
+        .map(|factory| factory.name().to_string())
+        .unwrap_or_default()
+}
+
+/// Sets the bitrate in bit/s, in the unit of the encoder
+fn set_encoder_bitrate(encoder: &gstreamer::Element, bitrate: u32) {
+    match factory_name(encoder).as_str() {
+        "openh264enc" => encoder.set_property("bitrate", bitrate),
+        "x264enc" => encoder.set_property("bitrate", bitrate / 1000),
+        _ => {
+            if encoder.has_property("bitrate", None) {
+                encoder.set_property_from_str("bitrate", &bitrate.to_string());
+            }
+        }
+    }
+}
+
+/// Sets the low latency options of the known encoders and the bitrate in bit/s
+fn configure_encoder(encoder: &gstreamer::Element, bitrate: u32, temporal_layers: u8) {
+    match factory_name(encoder).as_str() {
+        "openh264enc" => {
+            encoder.set_property_from_str("complexity", "low");
+            encoder.set_property("scene-change-detection", false);
+            encoder.set_property("background-detection", false);
+        }
+        "x264enc" => {
+            encoder.set_property_from_str("tune", "zerolatency");
+            encoder.set_property_from_str("speed-preset", "ultrafast");
+            // Format changes are signalled to the subscribers in-band by the
+            // SPS and PPS, so they have to precede every keyframe
+            let mut options = "repeat-headers=1".to_string();
+            // A fixed pattern of B frames between the P frames, see
+            // `h264::temporal_layer`. Each B frame delays the stream by a frame
+            match temporal_layers {
+                0 | 1 => (),
+                2 => options.push_str(":bframes=1:b-adapt=0:b-pyramid=none"),
+                _ => options.push_str(":bframes=3:b-adapt=0:b-pyramid=strict"),
+            }
+            encoder.set_property("option-string", options);
+        }
+        _ => (),
+    }
+    set_encoder_bitrate(encoder, bitrate);
+}
+
+/// Elements of the encode branch that can be adjusted while capturing, so
+/// that the published stream changes without affecting the preview
+#[derive(Clone)]
+pub struct CaptureControls {
+    encoder: gstreamer::Element,
+    valve: gstreamer::Element,
+    crop: gstreamer::Element,
+    crop_caps: gstreamer::Element,
+    balance: gstreamer::Element,
+    caps_filter: gstreamer::Element,
+}
+
+impl CaptureControls {
+    /// Bitrate in bit/s
+    pub fn set_bitrate(&self, bitrate: u32) {
+        set_encoder_bitrate(&self.encoder, bitrate);
+    }
+
+    /// Scales and converts the rate of the encoded frames. `None` keeps the
+    /// resolution or framerate of the camera.
+    pub fn set_format(&self, resolution: Option<(u32, u32)>, framerate: Option<u32>) {
+        let mut caps = gstreamer::Caps::builder("video/x-raw");
+        if let Some((width, height)) = resolution {
+            caps = caps
+                .field("width", width as i32)
+                .field("height", height as i32);
+        }
+        if let Some(framerate) = framerate {
+            caps = caps.field("framerate", gstreamer::Fraction::new(framerate as i32, 1));
+        }
+        self.caps_filter.set_property("caps", caps.build());
+    }
+
+    /// Crops the camera picture to the view of a digital PTZ camera and scales
+    /// the view back to the camera resolution. Nothing is cropped until the
+    /// camera format is negotiated.
+    pub fn set_view(&self, position: &PtzPosition) {
+        let Some(caps) = self
+            .crop
+            .static_pad("sink")
+            .and_then(|pad| pad.current_caps())
+        else {
+            return;
+        };
+        let Some(structure) = caps.structure(0) else {
+            return;
+        };
+        let width = structure.get::<i32>("width").unwrap_or_default();
+        let height = structure.get::<i32>("height").unwrap_or_default();
+        let (left, right, top, bottom) = position.crop(width, height);
+        self.crop.set_property("left", left);
+        self.crop.set_property("right", right);
+        self.crop.set_property("top", top);
+        self.crop.set_property("bottom", bottom);
+        let mut view_caps = gstreamer::Caps::builder("video/x-raw");
+        if left + right + top + bottom > 0 {
+            view_caps = view_caps.field("width", width).field("height", height);
+        }
+        self.crop_caps.set_property("caps", view_caps.build());
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.valve.property::<bool>("drop")
+    }
+
+    /// Stops encoding. When resumed, the encoder starts with a keyframe.
+    pub fn set_paused(&self, paused: bool) {
+        self.valve.set_property("drop", paused);
+        if !paused {
+            let force_keyframe = gstreamer::event::CustomUpstream::new(
+                gstreamer::Structure::builder("GstForceKeyUnit")
+                    .field("all-headers", true)
+                    .build(),
+            );
+            self.encoder.send_event(force_keyframe);
+        }
+    }
+
+    /// Encodes black frames instead of the camera picture
+    pub fn set_muted(&self, muted: bool) {
+        let (brightness, contrast, saturation) = if muted {
+            (-1.0, 0.0, 0.0)
+        } else {
+            (0.0, 1.0, 1.0)
+        };
+        self.balance.set_property("brightness", brightness);
+        self.balance.set_property("contrast", contrast);
+        self.balance.set_property("saturation", saturation);
+    }
+}
+
+/// Adds `videoconvert ! taginject ! sink` showing a window with the given
+/// title. Returns the first element of the chain.
+fn add_display(
+    pipeline: &gstreamer::Pipeline,
+    title: &str,
+    sink: &str,
+) -> Result<gstreamer::Element, Error> {
+    let convert = make("videoconvert")?;
+    let taginject = gstreamer::ElementFactory::make("taginject")
+        .property("tags", format!("title={}", title))
+        .build()?;
+    let sink = make(sink)?;
+    pipeline.add_many([&convert, &taginject, &sink])?;
+    gstreamer::Element::link_many([&convert, &taginject, &sink])?;
+    Ok(convert)
+}
+
+/// Local display of the camera frames of a capture pipeline
+pub enum Preview {
+    /// Window with the given title
+    Window(String),
+    /// Compositor shown in a window with the given title. The camera is
+    /// linked to the first compositor pad.
+    Compositor(String),
+}
+
+pub struct Compositor {
+    pub element: gstreamer::Element,
+    pub self_view_pad: gstreamer::Pad,
+}
+
+pub struct CapturePipeline {
+    pub pipeline: gstreamer::Pipeline,
+    pub appsink: gstreamer_app::AppSink,
+    /// Holds the latest controlled frame for snapshots, i.e. the frame as it
+    /// is encoded after pausing, muting and the PTZ view
+    pub snapshot_sink: gstreamer_app::AppSink,
+    /// Encoded frames of the simulcast layers, in the order of the layers
+    pub layer_sinks: Vec<gstreamer_app::AppSink>,
+    pub encoder: gstreamer::Element,
+    pub controls: CaptureControls,
+    pub compositor: Option<Compositor>,
+}
+
+/// Builds the `source ! tee ! encoder ! appsink` pipeline with a branch of
+/// the tee going to the preview. The encode branch passes a valve, a crop,
+/// scaling, rate conversion and a color balance, see `CaptureControls`, and
+/// then branches off to the snapshot sink. All elements are looked up in the
+/// plugin registry before the pipeline is built, the encoder is the first
+/// available one of the preference list.
+pub struct CapturePipelineBuilder {
+    source: String,
+    sink: String,
+    encoders: Vec<String>,
+    bitrate: u32,
+    layers: Vec<SimulcastLayer>,
+    temporal_layers: u8,
+    preview: Preview,
+}
+
+/// Adds `queue ! videoscale ! encoder ! appsink` encoding a simulcast layer
+/// of the frames of `tee`
+fn add_layer(
+    pipeline: &gstreamer::Pipeline,
+    tee: &gstreamer::Element,
+    encoder_factory: &str,
+    layer: &SimulcastLayer,
+) -> Result<gstreamer_app::AppSink, Error> {
+    let queue = make_leaky_queue()?;
+    let scale = make("videoscale")?;
+    let size_caps = make_capsfilter(
+        gstreamer::Caps::builder("video/x-raw")
+            .field("width", layer.width as i32)
+            .field("height", layer.height as i32)
+            .build(),
+    )?;
+    let convert = make("videoconvert")?;
+    let encoder = make(encoder_factory)?;
+    configure_encoder(&encoder, layer.bitrate, 1);
+    let encoded_caps = make_capsfilter(encoder_caps(1))?;
+    let appsink = gstreamer_app::AppSink::builder().sync(false).build();
+    let elements = [
+        &queue,
+        &scale,
+        &size_caps,
+        &convert,
+        &encoder,
+        &encoded_caps,
+        appsink.upcast_ref(),
+    ];
+    pipeline.add_many(elements)?;
+    gstreamer::Element::link_many(elements)?;
+    tee.link(&queue)?;
+    Ok(appsink)
+}
+
+impl CapturePipelineBuilder {
+    pub fn new(preview: Preview) -> Self {
+        Self {
+            source: "autovideosrc".to_string(),
+            sink: "autovideosink".to_string(),
+            encoders: H264_ENCODERS.iter().map(|e| e.to_string()).collect(),
+            bitrate: DEFAULT_BITRATE,
+            layers: Vec::new(),
+            temporal_layers: 1,
+            preview,
+        }
+    }
+
+    pub fn source(mut self, source: &str) -> Self {
+        self.source = source.to_string();
+        self
+    }
+
+    /// Sink of the preview window
+    pub fn sink(mut self, sink: &str) -> Self {
+        self.sink = sink.to_string();
+        self
+    }
+
+    /// Replaces the encoder preference list if `encoders` is not empty
+    pub fn encoders(mut self, encoders: Vec<String>) -> Self {
+        if !encoders.is_empty() {
+            self.encoders = encoders;
+        }
+        self
+    }
+
+    /// Bitrate in bit/s
+    pub fn bitrate(mut self, bitrate: u32) -> Self {
+        self.bitrate = bitrate;
+        self
+    }
+
+    /// Also encodes the simulcast `layers`, scaled from the main layer
+    pub fn layers(mut self, layers: Vec<SimulcastLayer>) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Number of temporal layers of the main layer, at most 3. They are built
+    /// from B frames, so with more than one the stream is encoded by x264enc
+    /// in the main profile, the other encoders of the preference list are
+    /// skipped.
+    pub fn temporal_layers(mut self, temporal_layers: u8) -> Self {
+        self.temporal_layers = temporal_layers.clamp(1, MAX_TEMPORAL_LAYERS);
+        self
+    }
+
+    pub fn build(self) -> Result<CapturePipeline, Error> {
+        let encoder_factory = if self.temporal_layers > 1 {
+            let encoders: Vec<_> = self
+                .encoders
+                .iter()
+                .filter(|name| TEMPORAL_LAYER_ENCODERS.contains(&name.as_str()))
+                .cloned()
+                .collect();
+            if encoders.is_empty() {
+                return Err(Error(format!(
+                    "Temporal layers require {}, the encoders are limited to {}",
+                    TEMPORAL_LAYER_ENCODERS.join(" or "),
+                    self.encoders.join(", ")
+                )));
+            }
+            select_element("H.264 encoder with temporal layers", &encoders)?
+        } else {
+            select_element("H.264 encoder", &self.encoders)?
+        };
+        let mut required = vec![
+            self.source.as_str(),
+            "capsfilter",
+            "tee",
+            "queue",
+            "valve",
+            "videocrop",
+            "videoscale",
+            "videorate",
+            "videoconvert",
+            "videobalance",
+            "appsink",
+            "taginject",
+            self.sink.as_str(),
+        ];
+        if let Preview::Compositor(_) = self.preview {
+            required.push("compositor");
+        }
+        check_elements(&required)?;
+
+        let pipeline = gstreamer::Pipeline::new();
+        let source = make(&self.source)?;
+        let source_caps = make_capsfilter(camera_caps())?;
+        let tee = make("tee")?;
+        let encode_queue = make_leaky_queue()?;
+        let valve = make("valve")?;
+        let crop = make("videocrop")?;
+        let crop_scale = make("videoscale")?;
+        let crop_caps = make_capsfilter(gstreamer::Caps::builder("video/x-raw").build())?;
+        let scale = make("videoscale")?;
+        let rate = make("videorate")?;
+        let encode_convert = make("videoconvert")?;
+        let balance = make("videobalance")?;
+        let control_caps = make_capsfilter(gstreamer::Caps::builder("video/x-raw").build())?;
+        let control_tee = make("tee")?;
+        let encoder = make(&encoder_factory)?;
+        configure_encoder(&encoder, self.bitrate, self.temporal_layers);
+        let encoded_caps = make_capsfilter(encoder_caps(self.temporal_layers))?;
+        let appsink = gstreamer_app::AppSink::builder().sync(false).build();
+        let preview_queue = make_leaky_queue()?;
+        let snapshot_queue = make_leaky_queue()?;
+        let snapshot_sink = gstreamer_app::AppSink::builder()
+            .sync(false)
+            .max_buffers(1)
+            .drop(true)
+            .build();
+
+        pipeline.add_many([
+            &source,
+            &source_caps,
+            &tee,
+            &encode_queue,
+            &valve,
+            &crop,
+            &crop_scale,
+            &crop_caps,
+            &scale,
+            &rate,
+            &encode_convert,
+            &balance,
+            &control_caps,
+            &control_tee,
+            &encoder,
+            &encoded_caps,
+            appsink.upcast_ref(),
+            &preview_queue,
+            &snapshot_queue,
+            snapshot_sink.upcast_ref(),
+        ])?;
+        gstreamer::Element::link_many([&source, &source_caps, &tee])?;
+        gstreamer::Element::link_many([
+            &tee,
+            &encode_queue,
+            &valve,
+            &crop,
+            &crop_scale,
+            &crop_caps,
+            &scale,
+            &rate,
+            &encode_convert,
+            &balance,
+            &control_caps,
+            &control_tee,
+        ])?;
+        gstreamer::Element::link_many([&encoder, &encoded_caps, appsink.upcast_ref()])?;
+        // The layers and snapshots are taken from the controlled frames, so
+        // that pausing, muting and the PTZ view apply to all of them
+        let layer_sinks = if self.layers.is_empty() {
+            control_tee.link(&encoder)?;
+            Vec::new()
+        } else {
+            let main_queue = make_leaky_queue()?;
+            pipeline.add(&main_queue)?;
+            gstreamer::Element::link_many([&control_tee, &main_queue, &encoder])?;
+            self.layers
+                .iter()
+                .map(|layer| add_layer(&pipeline, &control_tee, &encoder_factory, layer))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        gstreamer::Element::link_many([&control_tee, &snapshot_queue, snapshot_sink.upcast_ref()])?;
+        tee.link(&preview_queue)?;
+
+        let compositor = match self.preview {
+            Preview::Window(title) => {
+                let display = add_display(&pipeline, &title, &self.sink)?;
+                preview_queue.link(&display)?;
+                None
+            }
+            Preview::Compositor(title) => {
+                let element = make("compositor")?;
+                element.set_property_from_str("background", "black");
+                pipeline.add(&element)?;
+                let display = add_display(&pipeline, &title, &self.sink)?;
+                element.link(&display)?;
+                let self_view_pad = element
+                    .request_pad_simple("sink_%u")
+                    .expect("Compositor has request pads");
+                preview_queue
+                    .static_pad("src")
+                    .expect("queue has src pad")
+                    .link(&self_view_pad)?;
+                Some(Compositor {
+                    element,
+                    self_view_pad,
+                })
+            }
+        };
+
+        println!("Encoding with {}", encoder_factory);
+        let controls = CaptureControls {
+            encoder: encoder.clone(),
+            valve,
+            crop,
+            crop_caps,
+            balance,
+            caps_filter: control_caps,
+        };
+        Ok(CapturePipeline {
+            pipeline,
+            appsink,
+            snapshot_sink,
+            layer_sinks,
+            encoder,
+            controls,
+            compositor,
+        })
+    }
+}
+
+/// Bin decoding the frames pushed into its appsrc to raw video on its src pad
+pub struct DecodeBranch {
+    pub bin: gstreamer::Bin,
+    pub appsrc: gstreamer_app::AppSrc,
+    pub decoder: gstreamer::Element,
+    convert: gstreamer::Element,
+    decoders: Vec<String>,
+    format: Option<SequenceParameterSet>,
+}
+
+impl DecodeBranch {
+    /// Uses the first available decoder of the `decoders` preference list,
+    /// or of `H264_DECODERS` if the list is empty
+    pub fn new(decoders: &[String]) -> Result<Self, Error> {
+        let decoders = if decoders.is_empty() {
+            H264_DECODERS.iter().map(|d| d.to_string()).collect()
+        } else {
+            decoders.to_vec()
+        };
+        let decoder_factory = select_element("H.264 decoder", &decoders)?;
+        check_elements(&["appsrc", "videoconvert"])?;
+
+        let bin = gstreamer::Bin::new();
+        let appsrc = gstreamer_app::AppSrc::builder()
+            .caps(&h264_caps())
+            .format(gstreamer::Format::Time)
+            .is_live(true)
+            .do_timestamp(true)
+            .build();
+        let decoder = make(&decoder_factory)?;
+        let convert = make("videoconvert")?;
+        bin.add_many([appsrc.upcast_ref(), &decoder, &convert])?;
+        gstreamer::Element::link_many([appsrc.upcast_ref(), &decoder, &convert])?;
+        let src_pad =
+            gstreamer::GhostPad::with_target(&convert.static_pad("src").expect("src pad"))?;
+        bin.add_pad(&src_pad)?;
+
+        Ok(Self {
+            bin,
+            appsrc,
+            decoder,
+            convert,
+            decoders,
+            format: None,
+        })
+    }
+
+    /// Sets the caps announced in the stream info of the stream
+    pub fn apply_stream_info(&mut self, stream_info: &StreamInfo) -> Result<(), Error> {
+        if stream_info.codec != "h264" {
+            return Err(Error(format!(
+                "Codec {:?} of user_id {} is not supported",
+                stream_info.codec, stream_info.user_id
+            )));
+        }
+        let caps = stream_info.caps.parse::<gstreamer::Caps>()?;
+        self.set_caps(caps)?;
+        println!(
+            "user_id {} ({}): {} {}x{} {}/{} fps",
+            stream_info.user_id,
+            stream_info.display_name,
+            stream_info.codec,
+            stream_info.width,
+            stream_info.height,
+            stream_info.framerate_num,
+            stream_info.framerate_den
+        );
+        Ok(())
+    }
+
+    /// Follows the format changes signalled in-band by the sequence parameter
+    /// set in front of each keyframe, so that a new resolution or profile is
+    /// picked up without waiting for the stream info.
+    pub fn update_format(&mut self, frame: &[u8]) -> Result<(), Error> {
+        let Some(sps) = h264::find_sps(frame) else {
+            return Ok(());
+        };
+        if self.format == Some(sps) {
+            return Ok(());
+        }
+        self.format = Some(sps);
+
+        let mut caps = self.appsrc.caps().unwrap_or_else(h264_caps);
+        {
+            let caps = caps.make_mut();
+            caps.set("width", sps.width as i32);
+            caps.set("height", sps.height as i32);
+            caps.set("profile", sps.profile_name());
+        }
+        self.set_caps(caps)
+    }
+
+    /// Sets new caps on the appsrc, so that the decoder and everything
+    /// downstream renegotiate, and replaces the decoder if it cannot handle them
+    fn set_caps(&mut self, caps: gstreamer::Caps) -> Result<(), Error> {
+        if self.appsrc.caps().as_ref() == Some(&caps) {
+            return Ok(());
+        }
+        let decoder_accepts_caps = self
+            .decoder
+            .factory()
+            .map_or(false, |factory| factory.can_sink_any_caps(&caps));
+        if !decoder_accepts_caps {
+            self.replace_decoder(&caps)?;
+        }
+        println!("Stream caps changed to {}", caps);
+        self.appsrc.set_caps(Some(&caps));
+        Ok(())
+    }
+
+    /// Swaps in the first decoder of the preference list accepting `caps`.
+    /// The swap happens once no buffer is flowing out of the appsrc.
+    fn replace_decoder(&mut self, caps: &gstreamer::Caps) -> Result<(), Error> {
+        let factory = self
+            .decoders
+            .iter()
+            .filter_map(|name| gstreamer::ElementFactory::find(name))
+            .find(|factory| factory.can_sink_any_caps(caps))
+            .ok_or_else(|| {
+                Error(format!(
+                    "None of the decoders {} can decode {}",
+                    self.decoders.join(", "),
+                    caps
+                ))
+            })?;
+        let new_decoder = factory.create().build()?;
+        let old_decoder = std::mem::replace(&mut self.decoder, new_decoder.clone());
+        let bin = self.bin.clone();
+        let convert = self.convert.clone();
+        println!("Switching decoder to {}", factory.name());
+
+        let appsrc_pad = self.appsrc.static_pad("src").expect("appsrc has src pad");
+        appsrc_pad.add_probe(gstreamer::PadProbeType::IDLE, move |pad, _| {
+            let swap = || -> Result<(), Error> {
+                old_decoder.set_state(gstreamer::State::Null)?;
+                bin.remove(&old_decoder)?;
+                bin.add(&new_decoder)?;
+                pad.link(
+                    &new_decoder
+                        .static_pad("sink")
+                        .expect("decoder has sink pad"),
+                )?;
+                new_decoder.link(&convert)?;
+                new_decoder.sync_state_with_parent()?;
+                Ok(())
+            };
+            if let Err(e) = swap() {
+                println!("Switching decoder failed: {}", e);
+            }
+            gstreamer::PadProbeReturn::Remove
+        });
+        Ok(())
+    }
+}
+
+/// Size of the tiles of the streams while more than one stream is shown
+const TILE_WIDTH: i32 = 640;
+const TILE_HEIGHT: i32 = 360;
+
+/// Places a compositor pad
+pub fn set_geometry(pad: &gstreamer::Pad, xpos: i32, ypos: i32, width: i32, height: i32) {
+    pad.set_property("xpos", xpos);
+    pad.set_property("ypos", ypos);
+    pad.set_property("width", width);
+    pad.set_property("height", height);
+}
+
+/// Shows a single stream in its own size and several streams as a grid of
+/// tiles in the order they were added
+fn layout(pads: &[gstreamer::Pad]) {
+    if let [pad] = pads {
+        set_geometry(pad, 0, 0, 0, 0);
+        return;
+    }
+    let columns = (pads.len() as f64).sqrt().ceil() as usize;
+    for (index, pad) in pads.iter().enumerate() {
+        set_geometry(
+            pad,
+            (index % columns) as i32 * TILE_WIDTH,
+            (index / columns) as i32 * TILE_HEIGHT,
+            TILE_WIDTH,
+            TILE_HEIGHT,
+        );
+    }
+}
+
+/// Elements and compositor pad of a stream added to the playback pipeline
+struct StreamBranch {
+    /// The decode bin first
+    elements: Vec<gstreamer::Element>,
+    compositor_pad: gstreamer::Pad,
+}
+
+/// Shows the streams added with `add_stream`, every stream decoded by its own
+/// branch feeding a pad of the compositor
+pub struct PlaybackPipeline {
+    pub pipeline: gstreamer::Pipeline,
+    pub overlay: gstreamer::Element,
+    compositor: gstreamer::Element,
+    decoders: Vec<String>,
+    annotations: Option<AnnotationOverlay>,
+    /// Funnel in front of every frame sink and the format it expects
+    frame_sinks: Mutex<Vec<(gstreamer::Element, RawFormat)>>,
+    /// Branches of the streams in the order they were added
+    streams: Mutex<Vec<StreamBranch>>,
+}
+
+impl PlaybackPipeline {
+    /// Adds an appsink receiving the decoded frames of all streams converted
+    /// to `format`. Frames are dropped while the appsink is full, so that a
+    /// slow consumer does not delay the display. Only the streams added
+    /// afterwards are connected to it.
+    pub fn add_frame_sink(&self, format: RawFormat) -> Result<gstreamer_app::AppSink, Error> {
+        let funnel = make("funnel")?;
+        let appsink = gstreamer_app::AppSink::builder()
+            .sync(false)
+            .max_buffers(FRAME_SINK_CAPACITY)
+            .drop(true)
+            .build();
+        self.pipeline.add_many([&funnel, appsink.upcast_ref()])?;
+        funnel.link(&appsink)?;
+        self.frame_sinks
+            .lock()
+            .expect("frame sinks lock not poisoned")
+            .push((funnel, format));
+        Ok(appsink)
+    }
+
+    /// Adds the `decoder ! tee ! [rsvgoverlay]` branch of a stream, linked
+    /// to a new compositor pad and through a leaky `queue ! videoconvert` to
+    /// every frame sink
+    pub fn add_stream(&self) -> Result<DecodeBranch, Error> {
+        let decode = DecodeBranch::new(&self.decoders)?;
+        let tee = make("tee")?;
+        self.pipeline.add_many([decode.bin.upcast_ref(), &tee])?;
+        decode.bin.link(&tee)?;
+        let mut elements = vec![decode.bin.clone().upcast(), tee.clone()];
+
+        // The display branch needs no queue, the frame sink branches start
+        // with a leaky one
+        let display = match &self.annotations {
+            Some(annotations) => {
+                let annotation_overlay = make("rsvgoverlay")?;
+                self.pipeline.add(&annotation_overlay)?;
+                tee.link(&annotation_overlay)?;
+                annotations.attach(&annotation_overlay);
+                elements.push(annotation_overlay.clone());
+                annotation_overlay
+            }
+            None => tee.clone(),
+        };
+        let compositor_pad = self
+            .compositor
+            .request_pad_simple("sink_%u")
+            .expect("Compositor has request pads");
+        display.link_pads(None, &self.compositor, Some(compositor_pad.name().as_str()))?;
+
+        for (funnel, format) in self
+            .frame_sinks
+            .lock()
+            .expect("frame sinks lock not poisoned")
+            .iter()
+        {
+            let queue = make_leaky_queue()?;
+            let convert = make("videoconvert")?;
+            let format_caps = make_capsfilter(
+                gstreamer::Caps::builder("video/x-raw")
+                    .field("format", format.caps_format())
+                    .build(),
+            )?;
+            let branch = [&queue, &convert, &format_caps];
+            self.pipeline.add_many(branch)?;
+            gstreamer::Element::link_many(branch)?;
+            tee.link(&queue)?;
+            format_caps.link(funnel)?;
+            elements.extend(branch.into_iter().cloned());
+        }
+
+        for element in elements.iter().rev() {
+            element.sync_state_with_parent()?;
+        }
+        let mut streams = self.streams.lock().expect("streams lock not poisoned");
+        streams.push(StreamBranch {
+            elements,
+            compositor_pad,
+        });
+        layout(&compositor_pads(&streams));
+        Ok(decode)
+    }
+
+    /// Removes the branch of a stream added with `add_stream`, e.g. after
+    /// pushing a frame into it failed, and lays out the remaining streams
+    pub fn remove_stream(&self, decode: DecodeBranch) {
+        let mut streams = self.streams.lock().expect("streams lock not poisoned");
+        let Some(index) = streams.iter().position(|stream| {
+            stream.elements[0] == *decode.bin.upcast_ref::<gstreamer::Element>()
+        }) else {
+            return;
+        };
+        let stream = streams.remove(index);
+        let funnels: Vec<_> = self
+            .frame_sinks
+            .lock()
+            .expect("frame sinks lock not poisoned")
+            .iter()
+            .map(|(funnel, _)| funnel.clone())
+            .collect();
+        for element in &stream.elements {
+            element.set_state(gstreamer::State::Null).ok();
+            // The frame sink branches end on a request pad of their funnel
+            let Some(peer) = element.static_pad("src").and_then(|pad| pad.peer()) else {
+                continue;
+            };
+            if let Some(funnel) = peer
+                .parent_element()
+                .filter(|parent| funnels.contains(parent))
+            {
+                funnel.release_request_pad(&peer);
+            }
+        }
+        self.pipeline.remove_many(&stream.elements).ok();
+        self.compositor.release_request_pad(&stream.compositor_pad);
+        layout(&compositor_pads(&streams));
+    }
+}
+
+fn compositor_pads(streams: &[StreamBranch]) -> Vec<gstreamer::Pad> {
+    streams
+        .iter()
+        .map(|stream| stream.compositor_pad.clone())
+        .collect()
+}
+
+/// Builds the `compositor ! textoverlay ! sink` mirror of the capture
+/// pipeline, see `PlaybackPipeline::add_stream` for the branches of the
+/// streams. The text overlay starts silent.
+pub struct PlaybackPipelineBuilder {
+    decoders: Vec<String>,
+    title: String,
+    sink: String,
+    annotations: Option<AnnotationOverlay>,
+}
+
+impl PlaybackPipelineBuilder {
+    pub fn new(title: &str) -> Self {
+        Self {
+            decoders: H264_DECODERS.iter().map(|d| d.to_string()).collect(),
+            title: title.to_string(),
+            sink: "autovideosink".to_string(),
+            annotations: None,
+        }
+    }
+
+    /// Draws the annotations on every stream with an `rsvgoverlay`
+    pub fn annotations(mut self, annotations: Option<AnnotationOverlay>) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    pub fn sink(mut self, sink: &str) -> Self {
+        self.sink = sink.to_string();
+        self
+    }
+
+    /// Replaces the decoder preference list if `decoders` is not empty
+    pub fn decoders(mut self, decoders: Vec<String>) -> Self {
+        if !decoders.is_empty() {
+            self.decoders = decoders;
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<PlaybackPipeline, Error> {
+        let mut required = vec![
+            "compositor",
+            "videotestsrc",
+            "tee",
+            "funnel",
+            "textoverlay",
+            "videoconvert",
+            "taginject",
+            self.sink.as_str(),
+        ];
+        if self.annotations.is_some() {
+            required.push("rsvgoverlay");
+        }
+        check_elements(&required)?;
+        // Fails early if none of the decoders is available
+        select_element("H.264 decoder", &self.decoders)?;
+
+        let pipeline = gstreamer::Pipeline::new();
+        let compositor = make("compositor")?;
+        compositor.set_property_from_str("background", "black");
+        // Keeps the compositor running until the first stream arrives
+        let background = gstreamer::ElementFactory::make("videotestsrc")
+            .property("is-live", true)
+            .build()?;
+        background.set_property_from_str("pattern", "black");
+        let background_caps = make_capsfilter(
+            gstreamer::Caps::builder("video/x-raw")
+                .field("width", 16)
+                .field("height", 16)
+                .build(),
+        )?;
+        let overlay = gstreamer::ElementFactory::make("textoverlay")
+            .property("silent", true)
+            .property("font-desc", "Sans, 12")
+            .build()?;
+        overlay.set_property_from_str("valignment", "top");
+        overlay.set_property_from_str("halignment", "left");
+        pipeline.add_many([&background, &background_caps, &compositor, &overlay])?;
+        gstreamer::Element::link_many([&background, &background_caps, &compositor, &overlay])?;
+        let display = add_display(&pipeline, &self.title, &self.sink)?;
+        overlay.link(&display)?;
+
+        Ok(PlaybackPipeline {
+            pipeline,
+            overlay,
+            compositor,
+            decoders: self.decoders,
+            annotations: self.annotations,
+            frame_sinks: Mutex::new(Vec::new()),
+            streams: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+/// Encodes a raw video sample to a still image with the `encoder` element,
+/// e.g. `jpegenc`, scaled to the `size` in width and height if given
+pub fn encode_image(
+    sample: &gstreamer::Sample,
+    encoder: &str,
+    size: Option<(u32, u32)>,
+) -> Result<Vec<u8>, Error> {
+    check_elements(&["appsrc", "videoconvert", "videoscale", "appsink", encoder])?;
+    let caps = sample
+        .caps_owned()
+        .ok_or_else(|| Error("Sample has no caps".to_string()))?;
+    let buffer = sample
+        .buffer_owned()
+        .ok_or_else(|| Error("Sample has no buffer".to_string()))?;
+
+    let pipeline = gstreamer::Pipeline::new();
+    let appsrc = gstreamer_app::AppSrc::builder()
+        .caps(&caps)
+        .format(gstreamer::Format::Time)
+        .build();
+    let convert = make("videoconvert")?;
+    let scale = make("videoscale")?;
+    let mut size_caps = gstreamer::Caps::builder("video/x-raw");
+    if let Some((width, height)) = size {
+        size_caps = size_caps
+            .field("width", width as i32)
+            .field("height", height as i32);
+    }
+    let size_caps = make_capsfilter(size_caps.build())?;
+    let encoder = make(encoder)?;
+    let appsink = gstreamer_app::AppSink::builder().sync(false).build();
+    pipeline.add_many([
+        appsrc.upcast_ref(),
+        &convert,
+        &scale,
+        &size_caps,
+        &encoder,
+        appsink.upcast_ref(),
+    ])?;
+    gstreamer::Element::link_many([
+        appsrc.upcast_ref(),
+        &convert,
+        &scale,
+        &size_caps,
+        &encoder,
+        appsink.upcast_ref(),
+    ])?;
+
+    pipeline.set_state(gstreamer::State::Playing)?;
+    let image = appsrc
+        .push_buffer(buffer)
+        .map_err(|e| Error(format!("Pushing the frame failed: {:?}", e)))
+        .and_then(|_| {
+            appsrc
+                .end_of_stream()
+                .map_err(|e| Error(format!("Ending the stream failed: {:?}", e)))?;
+            appsink
+                .try_pull_sample(gstreamer::ClockTime::from_seconds(5))
+                .ok_or_else(|| Error("Encoding the image timed out".to_string()))
+        })
+        .and_then(|image| {
+            let buffer = image
+                .buffer()
+                .ok_or_else(|| Error("Image has no buffer".to_string()))?;
+            let map = buffer.map_readable()?;
+            Ok(map.to_vec())
+        });
+    pipeline.set_state(gstreamer::State::Null)?;
+    image
+}
+
+/// Calls `f` with every navigation event, e.g. a key press or mouse click,
+/// sent upstream by the video window behind `element`, together with the
+/// size of the video shown
+pub fn connect_navigation(
+    element: &gstreamer::Element,
+    f: impl Fn(&gstreamer::StructureRef, Option<(i32, i32)>) + Send + Sync + 'static,
+) {
+    let pad = element.static_pad("src").expect("element has src pad");
+    pad.add_probe(gstreamer::PadProbeType::EVENT_UPSTREAM, move |pad, info| {
+        if let Some(gstreamer::PadProbeData::Event(event)) = &info.data {
+            if event.type_() == gstreamer::EventType::Navigation {
+                if let Some(structure) = event.structure() {
+                    let size = pad.current_caps().and_then(|caps| {
+                        let structure = caps.structure(0)?;
+                        Some((
+                            structure.get::<i32>("width").ok()?,
+                            structure.get::<i32>("height").ok()?,
+                        ))
+                    });
+                    f(structure, size);
+                }
+            }
+        }
+        gstreamer::PadProbeReturn::Ok
+    });
+}
+
+/// Hands every frame arriving at the appsink to a sender thread writing it
+/// with the `writers` as sample of `user_id`. The frames are queued in
+/// `queue`, so that blocking writes do not stall the pipeline. With a `pacer`
+/// the writes are paced to its bandwidth limit. Every frame is marked with its
+/// layer out of the `temporal_layers` the stream is encoded with.
+pub fn publish_frames(
+    appsink: &gstreamer_app::AppSink,
+    mut writers: FrameWriters,
+    pacer: Option<Arc<Mutex<Pacer>>>,
+    queue: Arc<SendQueue>,
+    user_id: i16,
+    temporal_layers: u8,
+) {
+    let sender_queue = queue.clone();
+    let sender_pacer = pacer.clone();
+    std::thread::spawn(move || {
+        let pace = |size: usize| {
+            if let Some(pacer) = &sender_pacer {
+                let wait = pacer
+                    .lock()
+                    .expect("pacer lock not poisoned")
+                    .reserve(size, Instant::now());
+                std::thread::sleep(wait);
+            }
+        };
+        loop {
+            let queued = sender_queue.pop();
+            if writers.write(user_id, &queued, pace) {
+                println!("Wrote sample {:?}", queued.frame_num + 1);
+            } else {
+                sender_queue.record_failed_write();
+            }
+            use std::io::{self, Write};
+            io::stdout().flush().ok();
+        }
+    });
+
+    let mut i = 0;
+    appsink.set_callbacks(
+        gstreamer_app::AppSinkCallbacks::builder()
+            .new_sample(move |s| {
+                if let Ok(sample) = s.pull_sample() {
+                    let bytes = sample
+                        .buffer()
+                        .expect("buffer exists")
+                        .map_readable()
+                        .expect("readable buffer");
+
+                    if let Some(pacer) = &pacer {
+                        let is_reference = h264::is_reference(bytes.as_slice());
+                        let mut pacer = pacer.lock().expect("pacer lock not poisoned");
+                        if !pacer.admit(is_reference, Instant::now()) {
+                            println!("Dropped a non-reference frame over the bandwidth limit");
+                            return Ok(gstreamer::FlowSuccess::Ok);
+                        }
+                    }
+
+                    queue.push(QueuedFrame {
+                        frame_num: i,
+                        temporal_layer: h264::temporal_layer(bytes.as_slice(), temporal_layers),
+                        frame: bytes.to_vec(),
+                        is_keyframe: h264::is_keyframe(bytes.as_slice()),
+                    });
+                    i += 1;
+                }
+
+                Ok(gstreamer::FlowSuccess::Ok)
+            })
+            .build(),
+    );
+}
+
+/// Caps of the reference timestamp meta identifying the frame of a buffer.
+/// The decoders copy the meta to the decoded buffers.
+const FRAME_TAG: &str = "timestamp/x-voda-frame";
+
+/// Copies the frame into a buffer tagged with its `user_id` and `frame_num`
+fn frame_buffer(video: &Video) -> gstreamer::Buffer {
+    let mut buffer =
+        gstreamer::Buffer::with_size(video.frame.len()).expect("buffer creation failed");
+    {
+        let buffer_ref = buffer.get_mut().expect("mutable buffer");
+        {
+            let mut buffer_samples = buffer_ref.map_writable().expect("writeable buffer");
+            buffer_samples.clone_from_slice(video.frame);
+        }
+        let tag = gstreamer::Caps::builder(FRAME_TAG)
+            .field("user-id", video.user_id as i32)
+            .build();
+        gstreamer::ReferenceTimestampMeta::add(
+            buffer_ref,
+            &tag,
+            gstreamer::ClockTime::from_nseconds(video.frame_num as u32 as u64),
+            gstreamer::ClockTime::NONE,
+        );
+    }
+    buffer
+}
+
+/// `user_id` and `frame_num` of the frame a buffer was pushed or decoded from
+pub fn frame_tag(buffer: &gstreamer::BufferRef) -> Option<(i16, i32)> {
+    buffer
+        .iter_meta::<gstreamer::ReferenceTimestampMeta>()
+        .find_map(|meta| {
+            let structure = meta.reference().structure(0)?;
+            if structure.name() != FRAME_TAG {
+                return None;
+            }
+            let user_id = structure.get::<i32>("user-id").ok()?;
+            Some((user_id as i16, meta.timestamp().nseconds() as u32 as i32))
+        })
+}
+
+/// Copies a received frame into a new buffer and pushes it into the appsrc.
+/// Fails once the branch of the appsrc stopped, e.g. on a decoder error.
+pub fn push_frame(
+    appsrc: &gstreamer_app::AppSrc,
+    video: &Video,
+) -> Result<(), gstreamer::FlowError> {
+    appsrc.push_buffer(frame_buffer(video)).map(|_| ())
+}
+
+/// Pushes a frame timestamped to be shown at `playout`. The appsrc must not
+/// timestamp the buffers itself, i.e. have `do-timestamp` disabled.
+pub fn push_frame_at(
+    appsrc: &gstreamer_app::AppSrc,
+    video: &Video,
+    playout: Instant,
+) -> Result<(), gstreamer::FlowError> {
+    let mut buffer = frame_buffer(video);
+    let running_time = appsrc
+        .clock()
+        .and_then(|clock| clock.time())
+        .zip(appsrc.base_time())
+        .map(|(now, base_time)| now.saturating_sub(base_time));
+    if let Some(running_time) = running_time {
+        let delay = playout.saturating_duration_since(Instant::now());
+        let pts = running_time + gstreamer::ClockTime::from_nseconds(delay.as_nanos() as u64);
+        buffer.get_mut().expect("mutable buffer").set_pts(pts);
+    }
+    appsrc.push_buffer(buffer).map(|_| ())
+}
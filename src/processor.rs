@@ -0,0 +1,366 @@
+use crate::{
+    annotation::{Annotation, Region},
+    error::Error,
+    pipeline::frame_tag,
+};
+use dust_dds::publication::data_writer::DataWriter;
+use std::{collections::HashMap, time::Duration};
+
+pub const PROCESSOR_EVENT_TOPIC_NAME: &str = "ProcessorEvent";
+pub const PROCESSOR_EVENT_TYPE_NAME: &str = "ProcessorEvent";
+
+/// Names of the frame processors built into the subscriber
+pub const PROCESSORS: &[&str] = &["motion"];
+
+/// Pixel format of the frames handed to a frame processor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    /// Packed 8 bit red, green and blue
+    Rgb,
+    /// Planar 8 bit Y, U and V, with the U and V planes subsampled by 2 in
+    /// both directions
+    I420,
+}
+
+impl RawFormat {
+    /// Name of the format in GStreamer caps
+    pub fn caps_format(&self) -> &'static str {
+        match self {
+            RawFormat::Rgb => "RGB",
+            RawFormat::I420 => "I420",
+        }
+    }
+}
+
+/// Decoded frame of the stream of `user_id`, laid out as GStreamer lays out
+/// raw video by default: every row starts at a multiple of 4 bytes
+pub struct RawFrame<'a> {
+    pub user_id: i16,
+    pub frame_num: i32,
+    /// Running time of the playback pipeline at which the frame is shown
+    pub pts: Option<Duration>,
+    pub width: u32,
+    pub height: u32,
+    pub format: RawFormat,
+    pub data: &'a [u8],
+}
+
+fn round_up_4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+impl<'a> RawFrame<'a> {
+    /// Plane `index` and the number of bytes of its rows. RGB frames have one
+    /// plane, I420 frames the Y, U and V planes. As in GStreamer, the chroma
+    /// planes of odd sizes cover the pixels up to the next even size and
+    /// follow a luma plane of that even height.
+    pub fn plane(&self, index: usize) -> Option<(&'a [u8], usize)> {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let (offset, stride, rows) = match (self.format, index) {
+            (RawFormat::Rgb, 0) => (0, round_up_4(width * 3), height),
+            (RawFormat::I420, 0) => (0, round_up_4(width), height),
+            (RawFormat::I420, 1 | 2) => {
+                let luma = round_up_4(width) * ((height + 1) & !1);
+                let stride = round_up_4(width.div_ceil(2));
+                let rows = height.div_ceil(2);
+                (luma + (index - 1) * stride * rows, stride, rows)
+            }
+            _ => return None,
+        };
+        let plane = self.data.get(offset..offset + stride * rows)?;
+        Some((plane, stride))
+    }
+}
+
+/// Something a frame processor noticed in a stream, e.g. the start of motion
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct ProcessorEvent {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    /// Name of the frame processor
+    #[dust_dds(key)]
+    pub producer: String,
+    pub frame_num: i32,
+    pub kind: String,
+    pub message: String,
+}
+
+/// Lets a frame processor publish what it found in a frame. Annotations and
+/// events are dropped if the subscriber does not publish them.
+pub struct ProcessorOutput {
+    producer: String,
+    annotations: Option<DataWriter<Annotation>>,
+    events: Option<DataWriter<ProcessorEvent>>,
+}
+
+impl ProcessorOutput {
+    pub fn new(
+        producer: &str,
+        annotations: Option<DataWriter<Annotation>>,
+        events: Option<DataWriter<ProcessorEvent>>,
+    ) -> Self {
+        Self {
+            producer: producer.to_string(),
+            annotations,
+            events,
+        }
+    }
+
+    /// Publishes the `regions` found in the frame, drawn by the subscribers
+    /// showing annotations
+    pub fn annotate(&self, frame: &RawFrame, regions: &[Region]) {
+        if let Some(writer) = &self.annotations {
+            let annotation =
+                Annotation::new(frame.user_id, &self.producer, frame.frame_num, regions);
+            if let Err(e) = writer.write(&annotation, None) {
+                println!("Writing annotation failed: {:?}", e);
+            }
+        }
+    }
+
+    pub fn event(&self, frame: &RawFrame, kind: &str, message: String) {
+        println!(
+            "{} of user_id {} at frame {}: {} {}",
+            self.producer, frame.user_id, frame.frame_num, kind, message
+        );
+        if let Some(writer) = &self.events {
+            let event = ProcessorEvent {
+                user_id: frame.user_id,
+                producer: self.producer.clone(),
+                frame_num: frame.frame_num,
+                kind: kind.to_string(),
+                message,
+            };
+            if let Err(e) = writer.write(&event, None) {
+                println!("Writing processor event failed: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Analyzes the decoded frames of the subscriber. Every processor runs on its
+/// own worker thread and skips frames while it is busy.
+pub trait FrameProcessor: Send {
+    /// Names the producer of the annotations and events of the processor
+    fn name(&self) -> &str;
+
+    /// Format the frames are converted to for the processor
+    fn format(&self) -> RawFormat;
+
+    fn process(&mut self, frame: &RawFrame, output: &ProcessorOutput);
+}
+
+/// Built-in frame processor of the given name, see `PROCESSORS`
+pub fn create_processor(name: &str) -> Result<Box<dyn FrameProcessor>, Error> {
+    match name {
+        "motion" => Ok(Box::new(MotionDetector::default())),
+        _ => Err(Error(format!(
+            "Unknown frame processor {:?}, expected one of: {}",
+            name,
+            PROCESSORS.join(", ")
+        ))),
+    }
+}
+
+/// Hands every frame arriving at the `frame_sink` to the `processor` on a
+/// worker thread, until the pipeline stops
+pub fn spawn_processor(
+    frame_sink: gstreamer_app::AppSink,
+    mut processor: Box<dyn FrameProcessor>,
+    output: ProcessorOutput,
+) {
+    let format = processor.format();
+    std::thread::spawn(move || {
+        while let Ok(sample) = frame_sink.pull_sample() {
+            let Some(buffer) = sample.buffer() else {
+                continue;
+            };
+            let Some((user_id, frame_num)) = frame_tag(buffer) else {
+                continue;
+            };
+            let Some((width, height)) = sample.caps().and_then(|caps| {
+                let structure = caps.structure(0)?;
+                Some((
+                    structure.get::<i32>("width").ok()?,
+                    structure.get::<i32>("height").ok()?,
+                ))
+            }) else {
+                continue;
+            };
+            let Ok(map) = buffer.map_readable() else {
+                continue;
+            };
+            let frame = RawFrame {
+                user_id,
+                frame_num,
+                pts: buffer.pts().map(|pts| Duration::from_nanos(pts.nseconds())),
+                width: width as u32,
+                height: height as u32,
+                format,
+                data: map.as_slice(),
+            };
+            processor.process(&frame, &output);
+        }
+    });
+}
+
+/// Size of the cells compared between frames, in pixels
+const MOTION_CELL_SIZE: usize = 16;
+/// Mean difference of the luma of a cell, out of 255, counting as motion
+const MOTION_THRESHOLD: u32 = 24;
+
+/// Marks the area of the frame that changed since the previous frame of the
+/// stream and reports when motion starts and stops
+#[derive(Default)]
+pub struct MotionDetector {
+    /// Mean luma of every cell of the previous frame and whether it moved
+    streams: HashMap<i16, (Vec<u8>, bool)>,
+}
+
+impl FrameProcessor for MotionDetector {
+    fn name(&self) -> &str {
+        "motion"
+    }
+
+    fn format(&self) -> RawFormat {
+        RawFormat::I420
+    }
+
+    fn process(&mut self, frame: &RawFrame, output: &ProcessorOutput) {
+        let Some((luma, stride)) = frame.plane(0) else {
+            return;
+        };
+        let columns = frame.width as usize / MOTION_CELL_SIZE;
+        let rows = frame.height as usize / MOTION_CELL_SIZE;
+        let cells: Vec<u8> = (0..rows * columns)
+            .map(|cell| {
+                let (row, column) = (cell / columns, cell % columns);
+                let sum: u32 = (0..MOTION_CELL_SIZE)
+                    .flat_map(|y| {
+                        let start =
+                            (row * MOTION_CELL_SIZE + y) * stride + column * MOTION_CELL_SIZE;
+                        &luma[start..start + MOTION_CELL_SIZE]
+                    })
+                    .map(|&pixel| pixel as u32)
+                    .sum();
+                (sum / (MOTION_CELL_SIZE * MOTION_CELL_SIZE) as u32) as u8
+            })
+            .collect();
+
+        let (previous, moving) = self
+            .streams
+            .entry(frame.user_id)
+            .or_insert_with(|| (cells.clone(), false));
+        if previous.len() != cells.len() {
+            // The resolution changed, there is nothing to compare with
+            previous.clone_from(&cells);
+        }
+        // Bounding box of the moved cells as first and last row and column
+        let moved = cells
+            .iter()
+            .zip(previous.iter())
+            .enumerate()
+            .filter(|(_, (&cell, &before))| {
+                (cell as i32 - before as i32).unsigned_abs() > MOTION_THRESHOLD
+            })
+            .fold(
+                None,
+                |bounds: Option<(usize, usize, usize, usize)>, (cell, _)| {
+                    let (row, column) = (cell / columns, cell % columns);
+                    Some(match bounds {
+                        Some((top, left, bottom, right)) => (
+                            top.min(row),
+                            left.min(column),
+                            bottom.max(row),
+                            right.max(column),
+                        ),
+                        None => (row, column, row, column),
+                    })
+                },
+            );
+        *previous = cells;
+
+        let regions: Vec<_> = moved
+            .into_iter()
+            .map(|(top, left, bottom, right)| Region {
+                left: (left * MOTION_CELL_SIZE) as f64 / frame.width as f64,
+                top: (top * MOTION_CELL_SIZE) as f64 / frame.height as f64,
+                width: ((right - left + 1) * MOTION_CELL_SIZE) as f64 / frame.width as f64,
+                height: ((bottom - top + 1) * MOTION_CELL_SIZE) as f64 / frame.height as f64,
+                label: "motion".to_string(),
+            })
+            .collect();
+        output.annotate(frame, &regions);
+        if regions.is_empty() == *moving {
+            *moving = !regions.is_empty();
+            let kind = if *moving {
+                "motion-started"
+            } else {
+                "motion-stopped"
+            };
+            output.event(frame, kind, String::new());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(format: RawFormat, width: u32, height: u32, data: &[u8]) -> RawFrame<'_> {
+        RawFrame {
+            user_id: 1,
+            frame_num: 0,
+            pts: None,
+            width,
+            height,
+            format,
+            data,
+        }
+    }
+
+    /// Offset of the plane in the data and its stride
+    fn layout(frame: &RawFrame, index: usize) -> Option<(usize, usize)> {
+        let (plane, stride) = frame.plane(index)?;
+        Some((
+            plane.as_ptr() as usize - frame.data.as_ptr() as usize,
+            stride,
+        ))
+    }
+
+    #[test]
+    fn rgb_rows_start_on_multiples_of_4() {
+        let data = [0; 24];
+        let rgb = frame(RawFormat::Rgb, 3, 2, &data);
+        assert_eq!(layout(&rgb, 0), Some((0, 12)));
+        assert_eq!(rgb.plane(0).unwrap().0.len(), 24);
+        assert_eq!(layout(&rgb, 1), None);
+    }
+
+    #[test]
+    fn i420_planes_follow_each_other() {
+        let data = vec![0; 640 * 480 * 3 / 2];
+        let i420 = frame(RawFormat::I420, 640, 480, &data);
+        assert_eq!(layout(&i420, 0), Some((0, 640)));
+        assert_eq!(layout(&i420, 1), Some((307_200, 320)));
+        assert_eq!(layout(&i420, 2), Some((384_000, 320)));
+        assert_eq!(layout(&i420, 3), None);
+    }
+
+    #[test]
+    fn i420_planes_of_odd_sizes_are_rounded_up() {
+        // As GStreamer lays out 5x3 frames: a luma plane of 8 x 4 bytes
+        // and chroma planes of 4 x 2 bytes
+        let data = [0; 48];
+        let i420 = frame(RawFormat::I420, 5, 3, &data);
+        assert_eq!(layout(&i420, 0), Some((0, 8)));
+        assert_eq!(i420.plane(0).unwrap().0.len(), 24);
+        assert_eq!(layout(&i420, 1), Some((32, 4)));
+        assert_eq!(layout(&i420, 2), Some((40, 4)));
+        assert_eq!(i420.plane(2).unwrap().0.len(), 8);
+
+        let i420 = frame(RawFormat::I420, 7, 5, &data[..47]);
+        assert_eq!(layout(&i420, 1), None);
+    }
+}
@@ -0,0 +1,455 @@
+use crate::{error::Error, pipeline::CaptureControls};
+use dust_dds::{
+    publication::data_writer::DataWriter,
+    subscription::{
+        data_reader::DataReader,
+        data_reader_listener::DataReaderListener,
+        sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+pub const PTZ_CONTROL_TOPIC_NAME: &str = "PtzControl";
+pub const PTZ_CONTROL_TYPE_NAME: &str = "PtzControl";
+
+/// Announced in the stream info of publishers accepting PTZ requests
+pub const PTZ_CAPABILITY: &str = "digital-ptz";
+
+pub const MAX_ZOOM: f64 = 8.0;
+
+/// Pan and tilt of a navigation key press, in widths and heights of the view
+const NAVIGATION_STEP: f64 = 0.1;
+/// Zoom factor of a navigation key press or scroll step
+const NAVIGATION_ZOOM: f64 = 1.25;
+
+/// View of a digital PTZ camera. `pan` and `tilt` move the center of the view
+/// from the left or top edge (-1) to the right or bottom edge (1) of the
+/// camera picture, as far as the `zoom` leaves room for it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PtzPosition {
+    pub pan: f64,
+    pub tilt: f64,
+    pub zoom: f64,
+}
+
+impl Default for PtzPosition {
+    fn default() -> Self {
+        Self {
+            pan: 0.0,
+            tilt: 0.0,
+            zoom: 1.0,
+        }
+    }
+}
+
+impl PtzPosition {
+    /// Limits the zoom and keeps the view within the camera picture
+    fn clamped(self) -> Self {
+        let zoom = self.zoom.clamp(1.0, MAX_ZOOM);
+        let clamp = |offset: f64| {
+            if zoom > 1.0 {
+                offset.clamp(-1.0, 1.0)
+            } else {
+                0.0
+            }
+        };
+        Self {
+            pan: clamp(self.pan),
+            tilt: clamp(self.tilt),
+            zoom,
+        }
+    }
+
+    /// Moves by `pan` and `tilt` widths and heights of the current view and
+    /// multiplies the zoom by `zoom`
+    fn moved(self, pan: f64, tilt: f64, zoom: f64) -> Self {
+        let range = 1.0 - 1.0 / self.zoom;
+        let center = |offset: f64, delta: f64| offset * range + 2.0 * delta / self.zoom;
+        let zoom = (self.zoom * zoom).clamp(1.0, MAX_ZOOM);
+        let range = 1.0 - 1.0 / zoom;
+        let offset = |center: f64| {
+            if range > 0.0 {
+                center / range
+            } else {
+                0.0
+            }
+        };
+        Self {
+            pan: offset(center(self.pan, pan)),
+            tilt: offset(center(self.tilt, tilt)),
+            zoom,
+        }
+        .clamped()
+    }
+
+    /// Pixels to crop from the left, right, top and bottom of a `width` x
+    /// `height` picture. The offsets are even, as required by subsampled
+    /// formats.
+    pub fn crop(&self, width: i32, height: i32) -> (i32, i32, i32, i32) {
+        let even = |value: f64| (value / 2.0).round() as i32 * 2;
+        let axis = |size: i32, offset: f64| {
+            if size < 2 {
+                return (0, 0);
+            }
+            let view = even(f64::from(size) / self.zoom).clamp(2, size);
+            let margin = f64::from(size - view) / 2.0;
+            // Largest even start keeping the view within odd sizes too
+            let last_start = (size - view) / 2 * 2;
+            let start = even(margin + offset * margin).clamp(0, last_start);
+            (start, size - view - start)
+        };
+        let (left, right) = axis(width, self.pan);
+        let (top, bottom) = axis(height, self.tilt);
+        (left, right, top, bottom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PtzCommand {
+    Absolute(PtzPosition),
+    /// Moves by fractions of the current view and multiplies the zoom
+    Relative {
+        pan: f64,
+        tilt: f64,
+        zoom: f64,
+    },
+    /// Moves to the named preset
+    Preset(String),
+    /// Stores the current position as named preset
+    SavePreset(String),
+}
+
+impl PtzCommand {
+    /// Parses commands as entered on the standard input of the subscriber,
+    /// e.g. `absolute 0.5 0 2`, `relative 0.1 0 1` or `preset door`
+    pub fn parse(words: &[&str]) -> Result<Self, Error> {
+        let numbers = |words: &[&str]| -> Option<(f64, f64, f64)> {
+            match words {
+                [pan, tilt, zoom] => {
+                    Some((pan.parse().ok()?, tilt.parse().ok()?, zoom.parse().ok()?))
+                }
+                _ => None,
+            }
+        };
+        let command = match words {
+            ["absolute", rest @ ..] => numbers(rest)
+                .map(|(pan, tilt, zoom)| PtzCommand::Absolute(PtzPosition { pan, tilt, zoom })),
+            ["relative", rest @ ..] => {
+                numbers(rest).map(|(pan, tilt, zoom)| PtzCommand::Relative { pan, tilt, zoom })
+            }
+            ["preset", name] => Some(PtzCommand::Preset(name.to_string())),
+            ["save-preset", name] => Some(PtzCommand::SavePreset(name.to_string())),
+            _ => None,
+        };
+        command.ok_or_else(|| {
+            Error(format!(
+                "Invalid PTZ command {:?}, expected `absolute <pan> <tilt> <zoom>`, \
+                 `relative <pan> <tilt> <zoom factor>`, `preset <name>` or `save-preset <name>`",
+                words.join(" ")
+            ))
+        })
+    }
+
+    /// Command for a navigation event of the video window showing a `width`
+    /// x `height` stream: arrow keys pan and tilt, `+` and `-` or scrolling
+    /// zoom, a click centers the view on the clicked point, `Home` resets the
+    /// view and the digit keys move to the presets named after them.
+    pub fn from_navigation(
+        event: &gstreamer::StructureRef,
+        size: Option<(i32, i32)>,
+    ) -> Option<Self> {
+        let relative = |pan, tilt, zoom| Some(PtzCommand::Relative { pan, tilt, zoom });
+        match event.get::<&str>("event").ok()? {
+            "key-press" => match event.get::<&str>("key").ok()? {
+                "Left" => relative(-NAVIGATION_STEP, 0.0, 1.0),
+                "Right" => relative(NAVIGATION_STEP, 0.0, 1.0),
+                "Up" => relative(0.0, -NAVIGATION_STEP, 1.0),
+                "Down" => relative(0.0, NAVIGATION_STEP, 1.0),
+                "plus" | "equal" | "KP_Add" => relative(0.0, 0.0, NAVIGATION_ZOOM),
+                "minus" | "KP_Subtract" => relative(0.0, 0.0, 1.0 / NAVIGATION_ZOOM),
+                "Home" => Some(PtzCommand::Absolute(PtzPosition::default())),
+                key if key.len() == 1 && key.chars().all(|c| c.is_ascii_digit()) => {
+                    Some(PtzCommand::Preset(key.to_string()))
+                }
+                _ => None,
+            },
+            "mouse-button-press" if event.get::<i32>("button").ok()? == 1 => {
+                let (width, height) = size?;
+                let x = event.get::<f64>("pointer_x").ok()?;
+                let y = event.get::<f64>("pointer_y").ok()?;
+                relative(
+                    x / f64::from(width.max(1)) - 0.5,
+                    y / f64::from(height.max(1)) - 0.5,
+                    1.0,
+                )
+            }
+            "mouse-scroll" => {
+                let delta = event.get::<f64>("delta_pointer_y").ok()?;
+                if delta > 0.0 {
+                    relative(0.0, 0.0, NAVIGATION_ZOOM)
+                } else if delta < 0.0 {
+                    relative(0.0, 0.0, 1.0 / NAVIGATION_ZOOM)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// PTZ request of an operator for the stream of `user_id`
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct PtzControl {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    /// Host name of the requesting subscriber, as it claims it
+    #[dust_dds(key)]
+    pub operator: String,
+    pub sequence: u32,
+    /// `absolute`, `relative`, `preset` or `save-preset`
+    pub command: String,
+    pub pan: f64,
+    pub tilt: f64,
+    pub zoom: f64,
+    /// Name of the preset
+    pub preset: String,
+}
+
+impl PtzControl {
+    pub fn new(user_id: i16, sequence: u32, command: &PtzCommand) -> Self {
+        let mut control = Self {
+            user_id,
+            operator: gethostname::gethostname().to_string_lossy().into_owned(),
+            sequence,
+            command: String::new(),
+            pan: 0.0,
+            tilt: 0.0,
+            zoom: 1.0,
+            preset: String::new(),
+        };
+        match command {
+            PtzCommand::Absolute(position) => {
+                control.command = "absolute".to_string();
+                control.pan = position.pan;
+                control.tilt = position.tilt;
+                control.zoom = position.zoom;
+            }
+            &PtzCommand::Relative { pan, tilt, zoom } => {
+                control.command = "relative".to_string();
+                control.pan = pan;
+                control.tilt = tilt;
+                control.zoom = zoom;
+            }
+            PtzCommand::Preset(name) => {
+                control.command = "preset".to_string();
+                control.preset = name.clone();
+            }
+            PtzCommand::SavePreset(name) => {
+                control.command = "save-preset".to_string();
+                control.preset = name.clone();
+            }
+        }
+        control
+    }
+
+    pub fn command(&self) -> Result<PtzCommand, Error> {
+        let (pan, tilt, zoom) = (self.pan, self.tilt, self.zoom);
+        if !(pan.is_finite() && tilt.is_finite() && zoom.is_finite() && zoom > 0.0) {
+            return Err(Error("Invalid PTZ values".to_string()));
+        }
+        match self.command.as_str() {
+            "absolute" => Ok(PtzCommand::Absolute(PtzPosition { pan, tilt, zoom })),
+            "relative" => Ok(PtzCommand::Relative { pan, tilt, zoom }),
+            "preset" => Ok(PtzCommand::Preset(self.preset.clone())),
+            "save-preset" => Ok(PtzCommand::SavePreset(self.preset.clone())),
+            command => Err(Error(format!("Unknown PTZ command {:?}", command))),
+        }
+    }
+}
+
+/// Sends the PTZ requests of this host, numbered across all threads sending
+#[derive(Clone)]
+pub struct PtzSender {
+    writer: DataWriter<PtzControl>,
+    sequence: Arc<AtomicU32>,
+}
+
+impl PtzSender {
+    pub fn new(writer: DataWriter<PtzControl>) -> Self {
+        Self {
+            writer,
+            sequence: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    pub fn send(&self, user_id: i16, command: &PtzCommand) {
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        match self
+            .writer
+            .write(&PtzControl::new(user_id, sequence, command), None)
+        {
+            Ok(()) => println!(
+                "Sent PTZ request {} to user_id {}: {:?}",
+                sequence, user_id, command
+            ),
+            Err(e) => println!("Sending PTZ request failed: {:?}", e),
+        }
+    }
+}
+
+/// Moves the view of the capture pipeline on the requests of the authorized
+/// operators addressed to `user_id`
+pub struct PtzControlListener {
+    controls: CaptureControls,
+    operators: Vec<String>,
+    user_id: i16,
+    position: PtzPosition,
+    presets: BTreeMap<String, PtzPosition>,
+}
+
+impl PtzControlListener {
+    /// `operators` are the host names allowed to move the view, compared
+    /// with the unverified `operator` of the requests
+    pub fn new(
+        controls: CaptureControls,
+        operators: Vec<String>,
+        user_id: i16,
+        presets: BTreeMap<String, PtzPosition>,
+    ) -> Self {
+        Self {
+            controls,
+            operators,
+            user_id,
+            position: PtzPosition::default(),
+            presets,
+        }
+    }
+
+    fn handle(&mut self, request: &PtzControl) -> Result<(), Error> {
+        if !self.operators.contains(&request.operator) {
+            return Err(Error(format!(
+                "{} is not authorized to control the camera",
+                request.operator
+            )));
+        }
+        let position = match request.command()? {
+            PtzCommand::Absolute(position) => position.clamped(),
+            PtzCommand::Relative { pan, tilt, zoom } => self.position.moved(pan, tilt, zoom),
+            PtzCommand::Preset(name) => self
+                .presets
+                .get(&name)
+                .ok_or_else(|| Error(format!("Unknown preset {:?}", name)))?
+                .clamped(),
+            PtzCommand::SavePreset(name) => {
+                self.presets.insert(name, self.position);
+                self.position
+            }
+        };
+        self.position = position;
+        self.controls.set_view(&position);
+        Ok(())
+    }
+}
+
+impl<'a> DataReaderListener<'a> for PtzControlListener {
+    type Foo = PtzControl;
+
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
+            for sample in samples {
+                if let Ok(request) = sample.data() {
+                    if request.user_id != self.user_id {
+                        continue;
+                    }
+                    match self.handle(&request) {
+                        Ok(()) => println!(
+                            "PTZ request {} of {}: pan {:.2}, tilt {:.2}, zoom {:.2}",
+                            request.sequence,
+                            request.operator,
+                            self.position.pan,
+                            self.position.tilt,
+                            self.position.zoom
+                        ),
+                        Err(Error(message)) => println!(
+                            "PTZ request {} of {} rejected: {}",
+                            request.sequence, request.operator, message
+                        ),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(pan: f64, tilt: f64, zoom: f64) -> PtzPosition {
+        PtzPosition { pan, tilt, zoom }
+    }
+
+    #[test]
+    fn full_views_are_not_cropped() {
+        let full = PtzPosition::default();
+        assert_eq!(full.crop(640, 480), (0, 0, 0, 0));
+        assert_eq!(full.crop(641, 481), (0, 0, 0, 0));
+        assert_eq!(full.crop(1, 0), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn zoomed_views_are_cropped_around_their_center() {
+        assert_eq!(position(0.0, 0.0, 2.0).crop(640, 480), (160, 160, 120, 120));
+        assert_eq!(position(1.0, -1.0, 2.0).crop(640, 480), (320, 0, 0, 240));
+        assert_eq!(position(0.5, 0.0, 4.0).crop(640, 480), (360, 120, 180, 180));
+    }
+
+    #[test]
+    fn crop_offsets_stay_even_for_odd_sizes() {
+        assert_eq!(position(0.0, 0.0, 2.0).crop(641, 481), (160, 161, 120, 121));
+        assert_eq!(position(1.0, 1.0, 2.0).crop(641, 481), (320, 1, 240, 1));
+        assert_eq!(position(-1.0, -1.0, 2.0).crop(641, 481), (0, 321, 0, 241));
+        // The view is at least 2 pixels
+        assert_eq!(position(1.0, 1.0, MAX_ZOOM).crop(5, 3), (2, 1, 0, 1));
+    }
+
+    #[test]
+    fn moves_are_relative_to_the_current_view() {
+        let zoomed = PtzPosition::default().moved(0.0, 0.0, 2.0);
+        assert_eq!(zoomed, position(0.0, 0.0, 2.0));
+        // A quarter of the view is half the way to the edge at zoom 2
+        assert_eq!(zoomed.moved(0.25, -0.25, 1.0), position(0.5, -0.5, 2.0));
+        // The center stays put while zooming
+        let closer = position(0.5, 0.0, 2.0).moved(0.0, 0.0, 2.0);
+        assert_eq!(closer.zoom, 4.0);
+        assert!((closer.pan * 0.75 - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn moves_are_clamped_to_the_camera_picture() {
+        let full = PtzPosition::default();
+        // Without zoom there is no room to pan
+        assert_eq!(full.moved(0.5, 0.5, 1.0), full);
+        assert_eq!(full.moved(0.0, 0.0, 0.5), full);
+        assert_eq!(full.moved(0.0, 0.0, 100.0).zoom, MAX_ZOOM);
+        assert_eq!(
+            position(0.5, 0.0, 2.0).moved(2.0, -2.0, 1.0),
+            position(1.0, -1.0, 2.0)
+        );
+        assert_eq!(position(0.5, -0.5, 2.0).moved(0.0, 0.0, 0.5), full);
+    }
+}
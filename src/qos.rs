@@ -0,0 +1,287 @@
+use crate::fragment::MAX_FRAGMENT_COUNT;
+use dust_dds::infrastructure::{
+    qos::{DataReaderQos, DataWriterQos, DomainParticipantQos, PublisherQos, SubscriberQos},
+    qos_policy::{
+        DeadlineQosPolicy, DurabilityQosPolicy, DurabilityQosPolicyKind, HistoryQosPolicy,
+        HistoryQosPolicyKind, LivelinessQosPolicy, LivelinessQosPolicyKind, OwnershipQosPolicy,
+        OwnershipQosPolicyKind, OwnershipStrengthQosPolicy, PartitionQosPolicy,
+        ReliabilityQosPolicy, ReliabilityQosPolicyKind, UserDataQosPolicy,
+    },
+    time::{Duration, DurationKind},
+};
+
+/// Participant QoS announcing the host name as user data, so that
+/// `voda-list` can show where a stream comes from
+pub fn participant_qos() -> DomainParticipantQos {
+    DomainParticipantQos {
+        user_data: UserDataQosPolicy {
+            value: gethostname::gethostname()
+                .to_string_lossy()
+                .into_owned()
+                .into_bytes(),
+        },
+        ..Default::default()
+    }
+}
+
+/// Time after which a writer that stopped asserting its liveliness is
+/// considered lost. With exclusive ownership the readers switch to the next
+/// strongest writer once this expires.
+fn liveliness_lease_duration() -> DurationKind {
+    DurationKind::Finite(Duration::new(1, 0))
+}
+
+/// Number of frame intervals without a frame after which a stream is
+/// considered stalled
+const DEADLINE_FRAMES: u32 = 5;
+
+/// Deadline of a stream with the given framerate
+fn deadline(framerate: u32) -> DeadlineQosPolicy {
+    let period = std::time::Duration::from_secs(DEADLINE_FRAMES.into()) / framerate.max(1);
+    DeadlineQosPolicy {
+        period: DurationKind::Finite(Duration::new(
+            period.as_secs() as i32,
+            period.subsec_nanos(),
+        )),
+    }
+}
+
+fn automatic_liveliness() -> LivelinessQosPolicy {
+    LivelinessQosPolicy {
+        kind: LivelinessQosPolicyKind::Automatic,
+        lease_duration: liveliness_lease_duration(),
+    }
+}
+
+/// The writer QoS competing for exclusive ownership with the given strength.
+/// The samples that belong to a video stream, like its parity, keyframes and
+/// description, are written with the ownership of the video writer, so that
+/// the readers take them from the same of several redundant publishers.
+fn owned_writer_qos(mut qos: DataWriterQos, ownership_strength: Option<i32>) -> DataWriterQos {
+    if let Some(strength) = ownership_strength {
+        qos.ownership = OwnershipQosPolicy {
+            kind: OwnershipQosPolicyKind::Exclusive,
+        };
+        qos.ownership_strength = OwnershipStrengthQosPolicy { value: strength };
+        qos.liveliness = automatic_liveliness();
+    }
+    qos
+}
+
+fn owned_reader_qos(mut qos: DataReaderQos, exclusive_ownership: bool) -> DataReaderQos {
+    if exclusive_ownership {
+        qos.ownership = OwnershipQosPolicy {
+            kind: OwnershipQosPolicyKind::Exclusive,
+        };
+    }
+    qos
+}
+
+/// Video writer QoS. With an `ownership_strength` the writer competes with
+/// other writers of the same `user_id` for exclusive ownership. With a
+/// `framerate` the writer offers the deadline requested by monitoring readers.
+pub fn video_writer_qos(ownership_strength: Option<i32>, framerate: Option<u32>) -> DataWriterQos {
+    let mut qos = owned_writer_qos(DataWriterQos::default(), ownership_strength);
+    if let Some(framerate) = framerate {
+        qos.deadline = deadline(framerate);
+        qos.liveliness = automatic_liveliness();
+    }
+    qos
+}
+
+/// Video reader QoS. An exclusive reader only matches writers with exclusive
+/// ownership and only delivers samples of the strongest alive writer per `user_id`.
+/// With a `framerate` the reader requests a deadline and liveliness, so that
+/// stalled and lost streams are reported. Such a reader does not match
+/// writers without a deadline.
+pub fn video_reader_qos(exclusive_ownership: bool, framerate: Option<u32>) -> DataReaderQos {
+    let mut qos = owned_reader_qos(DataReaderQos::default(), exclusive_ownership);
+    if let Some(framerate) = framerate {
+        qos.deadline = deadline(framerate);
+        qos.liveliness = automatic_liveliness();
+    }
+    qos
+}
+
+/// Publisher QoS placing the streams of the publisher in the given rooms.
+/// Rooms are mapped to DDS partitions, without a room the default partition
+/// is used.
+pub fn publisher_qos(rooms: &[String]) -> PublisherQos {
+    PublisherQos {
+        partition: PartitionQosPolicy {
+            name: rooms.to_vec(),
+        },
+        ..Default::default()
+    }
+}
+
+/// Subscriber QoS joining the given rooms. Room names may contain the
+/// partition wildcards `*`, `?` and `[...]`, e.g. `building-a/*`.
+pub fn subscriber_qos(rooms: &[String]) -> SubscriberQos {
+    SubscriberQos {
+        partition: PartitionQosPolicy {
+            name: rooms.to_vec(),
+        },
+        ..Default::default()
+    }
+}
+
+fn reliable() -> ReliabilityQosPolicy {
+    ReliabilityQosPolicy {
+        kind: ReliabilityQosPolicyKind::Reliable,
+        max_blocking_time: DurationKind::Finite(Duration::new(0, 100_000_000)),
+    }
+}
+
+/// Writer QoS keeping the last sample of every instance for readers joining later
+fn latest_sample_writer_qos() -> DataWriterQos {
+    DataWriterQos {
+        reliability: reliable(),
+        durability: DurabilityQosPolicy {
+            kind: DurabilityQosPolicyKind::TransientLocal,
+        },
+        history: HistoryQosPolicy {
+            kind: HistoryQosPolicyKind::KeepLast(1),
+        },
+        ..Default::default()
+    }
+}
+
+fn latest_sample_reader_qos() -> DataReaderQos {
+    DataReaderQos {
+        reliability: reliable(),
+        durability: DurabilityQosPolicy {
+            kind: DurabilityQosPolicyKind::TransientLocal,
+        },
+        history: HistoryQosPolicy {
+            kind: HistoryQosPolicyKind::KeepLast(1),
+        },
+        ..Default::default()
+    }
+}
+
+/// Stream info writer QoS. The last description of every stream is kept for
+/// subscribers joining later.
+pub fn stream_info_writer_qos(ownership_strength: Option<i32>) -> DataWriterQos {
+    owned_writer_qos(latest_sample_writer_qos(), ownership_strength)
+}
+
+pub fn stream_info_reader_qos(exclusive_ownership: bool) -> DataReaderQos {
+    owned_reader_qos(latest_sample_reader_qos(), exclusive_ownership)
+}
+
+/// Keyframe writer QoS. Keyframes are delivered reliably, but not kept for
+/// readers joining later: the publisher writes the current keyframe when a
+/// reader matched, an older one would not fit the frames that follow.
+pub fn keyframe_writer_qos(ownership_strength: Option<i32>) -> DataWriterQos {
+    let qos = DataWriterQos {
+        reliability: reliable(),
+        ..Default::default()
+    };
+    owned_writer_qos(qos, ownership_strength)
+}
+
+pub fn keyframe_reader_qos(exclusive_ownership: bool) -> DataReaderQos {
+    let qos = DataReaderQos {
+        reliability: reliable(),
+        ..Default::default()
+    };
+    owned_reader_qos(qos, exclusive_ownership)
+}
+
+/// Parity writer QoS, owned like the video writer
+pub fn parity_writer_qos(ownership_strength: Option<i32>) -> DataWriterQos {
+    owned_writer_qos(DataWriterQos::default(), ownership_strength)
+}
+
+pub fn parity_reader_qos(exclusive_ownership: bool) -> DataReaderQos {
+    owned_reader_qos(DataReaderQos::default(), exclusive_ownership)
+}
+
+/// Simulcast info writer QoS. The last layer description of every stream is
+/// kept for subscribers joining later.
+pub fn simulcast_info_writer_qos() -> DataWriterQos {
+    latest_sample_writer_qos()
+}
+
+pub fn simulcast_info_reader_qos() -> DataReaderQos {
+    latest_sample_reader_qos()
+}
+
+/// Number of fragments kept per stream, all fragments of the largest frame
+/// that is reassembled
+const FRAGMENT_HISTORY_DEPTH: i32 = MAX_FRAGMENT_COUNT as i32;
+
+/// Fragment writer QoS. Like the video writer QoS, but keeping all fragments
+/// of a frame, which share the instance of their stream.
+pub fn fragment_writer_qos(
+    ownership_strength: Option<i32>,
+    framerate: Option<u32>,
+) -> DataWriterQos {
+    DataWriterQos {
+        history: HistoryQosPolicy {
+            kind: HistoryQosPolicyKind::KeepLast(FRAGMENT_HISTORY_DEPTH),
+        },
+        ..video_writer_qos(ownership_strength, framerate)
+    }
+}
+
+pub fn fragment_reader_qos(exclusive_ownership: bool, framerate: Option<u32>) -> DataReaderQos {
+    DataReaderQos {
+        history: HistoryQosPolicy {
+            kind: HistoryQosPolicyKind::KeepLast(FRAGMENT_HISTORY_DEPTH),
+        },
+        ..video_reader_qos(exclusive_ownership, framerate)
+    }
+}
+
+/// Number of control requests kept per operator and camera
+const CONTROL_HISTORY_DEPTH: i32 = 16;
+
+/// Camera control QoS. Requests are delivered reliably, but only to
+/// publishers running when they are sent.
+pub fn control_writer_qos() -> DataWriterQos {
+    DataWriterQos {
+        reliability: reliable(),
+        history: HistoryQosPolicy {
+            kind: HistoryQosPolicyKind::KeepLast(CONTROL_HISTORY_DEPTH),
+        },
+        ..Default::default()
+    }
+}
+
+pub fn control_reader_qos() -> DataReaderQos {
+    DataReaderQos {
+        reliability: reliable(),
+        history: HistoryQosPolicy {
+            kind: HistoryQosPolicyKind::KeepLast(CONTROL_HISTORY_DEPTH),
+        },
+        ..Default::default()
+    }
+}
+
+/// Camera state writer QoS. The last state of every camera is kept for
+/// subscribers joining later.
+pub fn camera_state_writer_qos() -> DataWriterQos {
+    latest_sample_writer_qos()
+}
+
+pub fn camera_state_reader_qos() -> DataReaderQos {
+    latest_sample_reader_qos()
+}
+
+/// Snapshot request and reply QoS. The last request of every client and its
+/// reply are delivered reliably.
+pub fn snapshot_writer_qos() -> DataWriterQos {
+    DataWriterQos {
+        reliability: reliable(),
+        ..Default::default()
+    }
+}
+
+pub fn snapshot_reader_qos() -> DataReaderQos {
+    DataReaderQos {
+        reliability: reliable(),
+        ..Default::default()
+    }
+}
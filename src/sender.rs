@@ -0,0 +1,243 @@
+use crate::{
+    fec::ParityEncoder,
+    fragment::Fragmenter,
+    h264::{self, ParameterSets},
+    video::Video,
+};
+use dust_dds::publication::data_writer::DataWriter;
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+};
+
+/// Encoded frame waiting to be written
+pub struct QueuedFrame {
+    pub frame_num: i32,
+    pub temporal_layer: u8,
+    pub frame: Vec<u8>,
+    pub is_keyframe: bool,
+}
+
+struct QueueState {
+    frames: VecDeque<QueuedFrame>,
+    max_depth: usize,
+    dropped_frames: u64,
+    failed_frames: u64,
+}
+
+/// Bounded queue between the GStreamer streaming thread and the sender
+/// thread writing the frames, so that blocking writes do not stall the
+/// capture and the preview.
+///
+/// When the queue is full the oldest delta frame is dropped. Keyframes are
+/// never dropped, the queue grows beyond its capacity if it only holds
+/// keyframes.
+pub struct SendQueue {
+    capacity: usize,
+    state: Mutex<QueueState>,
+    available: Condvar,
+}
+
+impl SendQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(QueueState {
+                frames: VecDeque::new(),
+                max_depth: 0,
+                dropped_frames: 0,
+                failed_frames: 0,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, frame: QueuedFrame) {
+        let mut state = self.state.lock().expect("send queue lock not poisoned");
+        if state.frames.len() >= self.capacity {
+            let oldest_delta = state.frames.iter().position(|queued| !queued.is_keyframe);
+            match oldest_delta {
+                Some(position) => {
+                    let dropped = state.frames.remove(position).expect("position is valid");
+                    state.dropped_frames += 1;
+                    println!("Send queue full, dropped frame {}", dropped.frame_num);
+                }
+                None if !frame.is_keyframe => {
+                    state.dropped_frames += 1;
+                    println!("Send queue full, dropped frame {}", frame.frame_num);
+                    return;
+                }
+                None => (),
+            }
+        }
+        state.frames.push_back(frame);
+        state.max_depth = state.max_depth.max(state.frames.len());
+        self.available.notify_one();
+    }
+
+    /// Waits for the next frame
+    pub fn pop(&self) -> QueuedFrame {
+        let mut state = self.state.lock().expect("send queue lock not poisoned");
+        loop {
+            if let Some(frame) = state.frames.pop_front() {
+                return frame;
+            }
+            state = self
+                .available
+                .wait(state)
+                .expect("send queue lock not poisoned");
+        }
+    }
+
+    /// Counts a frame popped from the queue that could not be written
+    pub fn record_failed_write(&self) {
+        self.state
+            .lock()
+            .expect("send queue lock not poisoned")
+            .failed_frames += 1;
+    }
+
+    /// Current state, as shown by the `stats` command
+    pub fn summary(&self) -> String {
+        let state = self.state.lock().expect("send queue lock not poisoned");
+        format!(
+            "send queue: {} of {} frames, at most {}\ndropped: {} frames\nfailed: {} frames",
+            state.frames.len(),
+            self.capacity,
+            state.max_depth,
+            state.dropped_frames,
+            state.failed_frames
+        )
+    }
+}
+
+/// Writers of the frames of one stream: the video writer, or a fragmenter,
+/// and optionally the keyframe writer for late joiners and the parity encoder
+pub struct FrameWriters {
+    writer: DataWriter<Video<'static>>,
+    fragmenter: Option<Fragmenter>,
+    keyframe_writer: Option<DataWriter<Video<'static>>>,
+    parity_encoder: Option<ParityEncoder>,
+    parameter_sets: ParameterSets,
+    /// Number and completed frame of the last keyframe
+    keyframe: Option<(i32, Vec<u8>)>,
+    /// Number of keyframe readers matched so far, all of which were served
+    /// the last keyframe
+    served_readers: i32,
+}
+
+impl FrameWriters {
+    pub fn new(writer: DataWriter<Video<'static>>) -> Self {
+        Self {
+            writer,
+            fragmenter: None,
+            keyframe_writer: None,
+            parity_encoder: None,
+            parameter_sets: ParameterSets::default(),
+            keyframe: None,
+            served_readers: 0,
+        }
+    }
+
+    /// Writes the frames fragmented instead of on the video topic
+    pub fn fragmenter(mut self, fragmenter: Option<Fragmenter>) -> Self {
+        self.fragmenter = fragmenter;
+        self
+    }
+
+    /// Also writes the last keyframe, completed with the parameter sets,
+    /// whenever a subscriber joined, so that it can start before the next
+    /// keyframe is due
+    pub fn keyframe_writer(mut self, keyframe_writer: Option<DataWriter<Video<'static>>>) -> Self {
+        self.keyframe_writer = keyframe_writer;
+        self
+    }
+
+    /// Protects the frames by parity
+    pub fn parity_encoder(mut self, parity_encoder: Option<ParityEncoder>) -> Self {
+        self.parity_encoder = parity_encoder;
+        self
+    }
+
+    /// Writes the frame, calling `pace` with the size of every sample before
+    /// writing it. Returns whether all its samples were written.
+    pub fn write(&mut self, user_id: i16, queued: &QueuedFrame, pace: impl Fn(usize)) -> bool {
+        let frame = queued.frame.as_slice();
+        let mut written = match &self.fragmenter {
+            Some(fragmenter) => fragmenter.write(
+                user_id,
+                queued.frame_num,
+                queued.temporal_layer,
+                frame,
+                &pace,
+            ),
+            None => {
+                let video_sample = Video {
+                    user_id,
+                    frame_num: queued.frame_num,
+                    temporal_layer: queued.temporal_layer,
+                    frame,
+                };
+                pace(frame.len());
+                match self.writer.write(&video_sample, None) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        println!("Writing frame {} failed: {:?}", queued.frame_num, e);
+                        false
+                    }
+                }
+            }
+        };
+
+        if let Some(parity_encoder) = &mut self.parity_encoder {
+            parity_encoder.add(queued.frame_num, queued.temporal_layer, frame);
+        }
+
+        self.parameter_sets.update(frame);
+        if self.keyframe_writer.is_some() && h264::is_keyframe(frame) {
+            let frame = self.parameter_sets.complete_keyframe(frame);
+            self.keyframe = Some((queued.frame_num, frame));
+        }
+        if !self.serve_keyframe(user_id, pace) {
+            written = false;
+        }
+        written
+    }
+
+    /// Writes the last keyframe if subscribers joined since it was last
+    /// written. Returns false if writing it failed.
+    fn serve_keyframe(&mut self, user_id: i16, pace: impl Fn(usize)) -> bool {
+        let (Some(keyframe_writer), Some((frame_num, frame))) =
+            (&self.keyframe_writer, &self.keyframe)
+        else {
+            return true;
+        };
+        let matched_readers = match keyframe_writer.get_publication_matched_status() {
+            Ok(status) => status.total_count,
+            Err(e) => {
+                println!("Reading the keyframe readers failed: {:?}", e);
+                return true;
+            }
+        };
+        if matched_readers <= self.served_readers {
+            return true;
+        }
+        let keyframe = Video {
+            user_id,
+            frame_num: *frame_num,
+            temporal_layer: 0,
+            frame,
+        };
+        pace(frame.len());
+        match keyframe_writer.write(&keyframe, None) {
+            Ok(()) => {
+                self.served_readers = matched_readers;
+                true
+            }
+            Err(e) => {
+                println!("Writing keyframe failed: {:?}", e);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use dust_dds::subscription::{
+    data_reader::DataReader,
+    data_reader_listener::DataReaderListener,
+    sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub const SIMULCAST_INFO_TOPIC_NAME: &str = "SimulcastInfo";
+pub const SIMULCAST_INFO_TYPE_NAME: &str = "SimulcastInfo";
+
+/// Announced in the stream info of publishers offering simulcast layers
+pub const SIMULCAST_CAPABILITY: &str = "simulcast";
+
+/// Largest number of layers of a stream, including the main layer
+pub const MAX_LAYERS: usize = 4;
+
+/// Time between two moves to a smaller layer, giving the decoder time to
+/// catch up after a move
+const STEP_DOWN_INTERVAL: Duration = Duration::from_secs(2);
+/// Time without trouble after which a layer dropped for performance reasons
+/// is tried again
+const STABLE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Name of the topic carrying `layer` of the streams of the `video_topic`.
+/// Layer 0 is the main stream on the video topic itself.
+pub fn layer_topic_name(video_topic: &str, layer: usize) -> String {
+    if layer == 0 {
+        video_topic.to_string()
+    } else {
+        format!("{}Layer{}", video_topic, layer)
+    }
+}
+
+/// Additional, usually smaller, encoding of the camera picture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulcastLayer {
+    pub width: u32,
+    pub height: u32,
+    /// Bitrate in bit/s
+    pub bitrate: u32,
+}
+
+/// Layers offered for a stream, announced with transient-local durability.
+/// Entry `i` describes layer `i`, a size of 0 stands for the camera resolution.
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct SimulcastInfo {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    pub widths: Vec<u32>,
+    pub heights: Vec<u32>,
+    pub bitrates: Vec<u32>,
+}
+
+impl SimulcastInfo {
+    /// Main layer of `bitrate` followed by the additional `layers`
+    pub fn new(user_id: i16, bitrate: u32, layers: &[SimulcastLayer]) -> Self {
+        let main = SimulcastLayer {
+            width: 0,
+            height: 0,
+            bitrate,
+        };
+        let layers: Vec<_> = std::iter::once(main)
+            .chain(layers.iter().copied())
+            .collect();
+        Self {
+            user_id,
+            widths: layers.iter().map(|layer| layer.width).collect(),
+            heights: layers.iter().map(|layer| layer.height).collect(),
+            bitrates: layers.iter().map(|layer| layer.bitrate).collect(),
+        }
+    }
+
+    pub fn layer_count(&self) -> usize {
+        self.heights.len().min(MAX_LAYERS)
+    }
+
+    /// Smallest layer at least `viewport_height` high, the main layer if
+    /// none is
+    fn layer_for_viewport(&self, viewport_height: u32) -> usize {
+        (1..self.layer_count())
+            .filter(|&layer| self.heights[layer] >= viewport_height)
+            .min_by_key(|&layer| self.heights[layer])
+            .unwrap_or(0)
+    }
+}
+
+/// Latest simulcast info of every `user_id`, shared between the simulcast
+/// info listener and the video listener
+#[derive(Clone, Default)]
+pub struct SimulcastInfoCache(Arc<Mutex<HashMap<i16, SimulcastInfo>>>);
+
+impl SimulcastInfoCache {
+    pub fn get(&self, user_id: i16) -> Option<SimulcastInfo> {
+        self.0
+            .lock()
+            .expect("simulcast info lock not poisoned")
+            .get(&user_id)
+            .cloned()
+    }
+
+    fn insert(&self, info: SimulcastInfo) {
+        self.0
+            .lock()
+            .expect("simulcast info lock not poisoned")
+            .insert(info.user_id, info);
+    }
+}
+
+/// Stores all received simulcast infos in the cache
+pub struct SimulcastInfoListener {
+    pub cache: SimulcastInfoCache,
+}
+
+impl<'a> DataReaderListener<'a> for SimulcastInfoListener {
+    type Foo = SimulcastInfo;
+
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
+            for sample in samples {
+                if let Ok(info) = sample.data() {
+                    self.cache.insert(info);
+                }
+            }
+        }
+    }
+}
+
+/// How a subscriber chooses the layer of the streams
+#[derive(Debug, Clone, Copy)]
+pub enum LayerSelection {
+    Fixed(usize),
+    /// Smallest layer covering the viewport height, or the main layer
+    /// without a viewport, moving to smaller layers while the subscriber
+    /// does not keep up
+    Auto {
+        viewport_height: Option<u32>,
+    },
+}
+
+/// What to do with a frame of a layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerDecision {
+    Show,
+    /// Show it, it is the first frame after a switch from another layer
+    Switched {
+        from: Option<usize>,
+    },
+    Ignore,
+}
+
+/// Layer shown of one stream. A switch to another layer takes effect on the
+/// next keyframe of that layer, until then the current layer is shown.
+pub struct LayerSwitch {
+    current: Option<usize>,
+    target: usize,
+    /// Smallest layer index allowed by the performance of the subscriber
+    floor: usize,
+    troubles: u64,
+    last_trouble: Instant,
+    last_floor_change: Instant,
+}
+
+impl LayerSwitch {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            current: None,
+            target: 0,
+            floor: 0,
+            troubles: 0,
+            last_trouble: now,
+            last_floor_change: now,
+        }
+    }
+
+    /// Chooses the target layer. `troubles` counts the frames so far that
+    /// were not shown in time, e.g. late or overflowing the decoding queue.
+    pub fn update(
+        &mut self,
+        selection: LayerSelection,
+        info: Option<&SimulcastInfo>,
+        troubles: u64,
+        now: Instant,
+    ) {
+        // Streams without simulcast info only have the main layer
+        let last_layer = info.map_or(0, |info| info.layer_count().saturating_sub(1));
+        self.target = match selection {
+            LayerSelection::Fixed(layer) => layer.min(last_layer),
+            LayerSelection::Auto { viewport_height } => {
+                if troubles > self.troubles {
+                    self.last_trouble = now;
+                    if self.floor < last_layer && now - self.last_floor_change > STEP_DOWN_INTERVAL
+                    {
+                        self.floor += 1;
+                        self.last_floor_change = now;
+                    }
+                } else if self.floor > 0
+                    && now - self.last_trouble > STABLE_PERIOD
+                    && now - self.last_floor_change > STABLE_PERIOD
+                {
+                    self.floor -= 1;
+                    self.last_floor_change = now;
+                }
+                let viewport_layer = match (info, viewport_height) {
+                    (Some(info), Some(height)) => info.layer_for_viewport(height),
+                    _ => 0,
+                };
+                viewport_layer.max(self.floor).min(last_layer)
+            }
+        };
+        self.troubles = troubles;
+    }
+
+    pub fn decide(&mut self, layer: usize, is_keyframe: bool) -> LayerDecision {
+        if self.current == Some(layer) {
+            return LayerDecision::Show;
+        }
+        // The main layer can be joined anywhere, its cached keyframe is shown first
+        let joinable = is_keyframe || (self.current.is_none() && layer == 0);
+        if layer == self.target && joinable {
+            let from = self.current.replace(layer);
+            return LayerDecision::Switched { from };
+        }
+        LayerDecision::Ignore
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Main layer with layers 360 and 180 pixels high
+    fn info() -> SimulcastInfo {
+        let layer = |height| SimulcastLayer {
+            width: height * 16 / 9,
+            height,
+            bitrate: height * 1000,
+        };
+        SimulcastInfo::new(1, 2_000_000, &[layer(360), layer(180)])
+    }
+
+    #[test]
+    fn viewports_get_the_smallest_layer_covering_them() {
+        let info = info();
+        assert_eq!(info.layer_for_viewport(100), 2);
+        assert_eq!(info.layer_for_viewport(180), 2);
+        assert_eq!(info.layer_for_viewport(181), 1);
+        assert_eq!(info.layer_for_viewport(360), 1);
+        assert_eq!(info.layer_for_viewport(361), 0);
+    }
+
+    #[test]
+    fn fixed_layers_are_limited_to_the_layers_offered() {
+        let now = Instant::now();
+        let mut layer_switch = LayerSwitch::new(now);
+        layer_switch.update(LayerSelection::Fixed(5), Some(&info()), 0, now);
+        assert_eq!(
+            layer_switch.decide(2, true),
+            LayerDecision::Switched { from: None }
+        );
+        // Without simulcast info only the main layer is offered
+        layer_switch.update(LayerSelection::Fixed(1), None, 0, now);
+        assert_eq!(layer_switch.decide(1, true), LayerDecision::Ignore);
+        assert_eq!(
+            layer_switch.decide(0, true),
+            LayerDecision::Switched { from: Some(2) }
+        );
+    }
+
+    #[test]
+    fn switches_wait_for_a_keyframe_of_the_new_layer() {
+        let now = Instant::now();
+        let info = info();
+        let mut layer_switch = LayerSwitch::new(now);
+        layer_switch.update(LayerSelection::Fixed(0), Some(&info), 0, now);
+        assert_eq!(
+            layer_switch.decide(0, false),
+            LayerDecision::Switched { from: None }
+        );
+
+        layer_switch.update(LayerSelection::Fixed(1), Some(&info), 0, now);
+        assert_eq!(layer_switch.decide(1, false), LayerDecision::Ignore);
+        assert_eq!(layer_switch.decide(0, false), LayerDecision::Show);
+        assert_eq!(
+            layer_switch.decide(1, true),
+            LayerDecision::Switched { from: Some(0) }
+        );
+        assert_eq!(layer_switch.decide(0, true), LayerDecision::Ignore);
+        assert_eq!(layer_switch.current(), Some(1));
+    }
+
+    #[test]
+    fn troubles_move_down_a_layer_until_the_stream_is_stable() {
+        let start = Instant::now();
+        let info = info();
+        let selection = LayerSelection::Auto {
+            viewport_height: None,
+        };
+        let mut layer_switch = LayerSwitch::new(start);
+        layer_switch.update(selection, Some(&info), 0, start);
+        assert_eq!(
+            layer_switch.decide(0, false),
+            LayerDecision::Switched { from: None }
+        );
+
+        // Down a layer, but only one per step down interval
+        let first_trouble = start + STEP_DOWN_INTERVAL + Duration::from_millis(1);
+        layer_switch.update(selection, Some(&info), 1, first_trouble);
+        let second_trouble = first_trouble + Duration::from_secs(1);
+        layer_switch.update(selection, Some(&info), 2, second_trouble);
+        assert_eq!(layer_switch.decide(2, true), LayerDecision::Ignore);
+        assert_eq!(
+            layer_switch.decide(1, true),
+            LayerDecision::Switched { from: Some(0) }
+        );
+
+        // Back up once no trouble was seen for the stable period
+        let stable = second_trouble + STABLE_PERIOD;
+        layer_switch.update(selection, Some(&info), 2, stable);
+        assert_eq!(layer_switch.decide(0, true), LayerDecision::Ignore);
+        let stable = stable + Duration::from_millis(1);
+        layer_switch.update(selection, Some(&info), 2, stable);
+        assert_eq!(layer_switch.decide(0, false), LayerDecision::Ignore);
+        assert_eq!(layer_switch.decide(1, false), LayerDecision::Show);
+        assert_eq!(
+            layer_switch.decide(0, true),
+            LayerDecision::Switched { from: Some(1) }
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{
+    control::MAX_DIMENSION,
+    error::Error,
+    pipeline::{encode_image, CaptureControls},
+};
+use dust_dds::{
+    publication::data_writer::DataWriter,
+    subscription::{
+        data_reader::DataReader,
+        data_reader_listener::DataReaderListener,
+        sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+    },
+};
+use std::sync::{mpsc, Arc};
+
+pub const SNAPSHOT_REQUEST_TOPIC_NAME: &str = "SnapshotRequest";
+pub const SNAPSHOT_REQUEST_TYPE_NAME: &str = "SnapshotRequest";
+pub const SNAPSHOT_REPLY_TOPIC_NAME: &str = "SnapshotReply";
+pub const SNAPSHOT_REPLY_TYPE_NAME: &str = "SnapshotReply";
+
+/// Announced in the stream info of publishers answering snapshot requests
+pub const SNAPSHOT_CAPABILITY: &str = "snapshot";
+
+/// Time to wait for a camera frame before the request fails
+const FRAME_TIMEOUT_SECS: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "jpeg" | "jpg" => Ok(ImageFormat::Jpeg),
+            "png" => Ok(ImageFormat::Png),
+            _ => Err(Error(format!(
+                "Unknown image format {:?}, expected `jpeg` or `png`",
+                name
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Png => "png",
+        }
+    }
+
+    fn encoder(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpegenc",
+            ImageFormat::Png => "pngenc",
+        }
+    }
+}
+
+/// Request for a still image of the stream of `user_id`
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct SnapshotRequest {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    /// Identifies the requesting client, the reply carries the same
+    #[dust_dds(key)]
+    pub client: String,
+    /// Host name of the requesting client, as it claims it
+    pub operator: String,
+    pub request_id: u32,
+    /// `jpeg` or `png`
+    pub format: String,
+    /// Both 0 to keep the camera resolution
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Still image answering a `SnapshotRequest`, or the reason it failed
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct SnapshotReply {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    #[dust_dds(key)]
+    pub client: String,
+    pub request_id: u32,
+    /// Empty if the image was taken
+    pub error: String,
+    pub format: String,
+    pub image: Vec<u8>,
+}
+
+/// Answers the snapshot requests of the authorized operators for `user_id`
+/// with the latest frame of the `snapshot_sink`. Requests are refused while
+/// the camera is paused. The frames are encoded on a worker thread, so that
+/// the DDS listener thread does not wait for the camera or the encoder.
+pub struct SnapshotListener {
+    user_id: i16,
+    requests: mpsc::Sender<SnapshotRequest>,
+    /// Replies to the requests the worker can no longer take
+    writer: Arc<DataWriter<SnapshotReply>>,
+}
+
+impl SnapshotListener {
+    /// `operators` are the host names allowed to request snapshots, compared
+    /// with the unverified `operator` of the requests
+    pub fn new(
+        snapshot_sink: gstreamer_app::AppSink,
+        controls: CaptureControls,
+        operators: Vec<String>,
+        writer: DataWriter<SnapshotReply>,
+        user_id: i16,
+    ) -> Self {
+        let (requests, receiver) = mpsc::channel::<SnapshotRequest>();
+        let writer = Arc::new(writer);
+        let worker_writer = writer.clone();
+        std::thread::spawn(move || {
+            let writer = worker_writer;
+            for request in receiver {
+                let result = if !operators.contains(&request.operator) {
+                    Err(Error(format!(
+                        "{} is not authorized to request snapshots",
+                        request.operator
+                    )))
+                } else if controls.is_paused() {
+                    Err(Error("The camera is paused".to_string()))
+                } else {
+                    take_snapshot(&snapshot_sink, &request)
+                };
+                let reply = match result {
+                    Ok(image) => {
+                        println!(
+                            "Snapshot {} for {}: {} bytes of {}",
+                            request.request_id,
+                            request.client,
+                            image.len(),
+                            request.format
+                        );
+                        SnapshotReply {
+                            user_id: request.user_id,
+                            client: request.client,
+                            request_id: request.request_id,
+                            error: String::new(),
+                            format: request.format,
+                            image,
+                        }
+                    }
+                    Err(Error(error)) => {
+                        println!(
+                            "Snapshot {} for {} failed: {}",
+                            request.request_id, request.client, error
+                        );
+                        SnapshotReply {
+                            user_id: request.user_id,
+                            client: request.client,
+                            request_id: request.request_id,
+                            error,
+                            format: request.format,
+                            image: Vec::new(),
+                        }
+                    }
+                };
+                if let Err(e) = writer.write(&reply, None) {
+                    println!("Writing snapshot failed: {:?}", e);
+                }
+            }
+        });
+        Self {
+            user_id,
+            requests,
+            writer,
+        }
+    }
+}
+
+fn take_snapshot(
+    snapshot_sink: &gstreamer_app::AppSink,
+    request: &SnapshotRequest,
+) -> Result<Vec<u8>, Error> {
+    let format = ImageFormat::parse(&request.format)?;
+    let size = image_size(request.width, request.height)?;
+    let sample = snapshot_sink
+        .try_pull_sample(gstreamer::ClockTime::from_seconds(FRAME_TIMEOUT_SECS))
+        .ok_or_else(|| Error("No camera frame available".to_string()))?;
+    encode_image(&sample, format.encoder(), size)
+}
+
+/// Requested size of the image, `None` to keep the camera resolution
+fn image_size(width: u32, height: u32) -> Result<Option<(u32, u32)>, Error> {
+    match (width, height) {
+        (0, 0) => Ok(None),
+        (1..=MAX_DIMENSION, 1..=MAX_DIMENSION) => Ok(Some((width, height))),
+        _ => Err(Error(format!(
+            "Invalid image size {}x{}, expected both sizes between 1 and {}, or both 0",
+            width, height, MAX_DIMENSION
+        ))),
+    }
+}
+
+impl<'a> DataReaderListener<'a> for SnapshotListener {
+    type Foo = SnapshotRequest;
+
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
+            for sample in samples {
+                if let Ok(request) = sample.data() {
+                    if request.user_id == self.user_id {
+                        if let Err(mpsc::SendError(request)) = self.requests.send(request) {
+                            println!(
+                                "Snapshot {} for {} failed: the snapshot worker stopped",
+                                request.request_id, request.client
+                            );
+                            let reply = SnapshotReply {
+                                user_id: request.user_id,
+                                client: request.client,
+                                request_id: request.request_id,
+                                error: "The snapshot worker stopped".to_string(),
+                                format: request.format,
+                                image: Vec::new(),
+                            };
+                            if let Err(e) = self.writer.write(&reply, None) {
+                                println!("Writing snapshot failed: {:?}", e);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
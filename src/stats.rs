@@ -0,0 +1,287 @@
+use dust_dds::infrastructure::time::Time;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Frames older than this are not taken into account for the rates
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Receive statistics of one video stream
+#[derive(Clone)]
+pub struct StreamStatistics {
+    user_id: i16,
+    last_frame_num: Option<i32>,
+    dropped_frames: u64,
+    late_frames: u64,
+    discarded_frames: u64,
+    recovered_frames: u64,
+    unrecoverable_frames: u64,
+    incomplete_frames: u64,
+    overflow_frames: u64,
+    deadline_missed: u64,
+    liveliness_lost: u64,
+    latency: Option<Duration>,
+    window: VecDeque<(Instant, usize)>,
+}
+
+impl StreamStatistics {
+    pub fn new(user_id: i16) -> Self {
+        Self {
+            user_id,
+            last_frame_num: None,
+            dropped_frames: 0,
+            late_frames: 0,
+            discarded_frames: 0,
+            recovered_frames: 0,
+            unrecoverable_frames: 0,
+            incomplete_frames: 0,
+            overflow_frames: 0,
+            deadline_missed: 0,
+            liveliness_lost: 0,
+            latency: None,
+            window: VecDeque::new(),
+        }
+    }
+
+    /// Records a received frame. Frame numbers skipped since the previous
+    /// frame are counted as dropped.
+    pub fn record(&mut self, frame_num: i32, frame_size: usize, source_timestamp: Option<Time>) {
+        self.record_at(frame_num, frame_size, source_timestamp, Instant::now());
+    }
+
+    fn record_at(
+        &mut self,
+        frame_num: i32,
+        frame_size: usize,
+        source_timestamp: Option<Time>,
+        now: Instant,
+    ) {
+        if let Some(last_frame_num) = self.last_frame_num {
+            let skipped = frame_num.wrapping_sub(last_frame_num).wrapping_sub(1);
+            if skipped > 0 {
+                self.dropped_frames += skipped as u64;
+            }
+        }
+        self.last_frame_num = Some(frame_num);
+
+        // The latency is only meaningful if the clocks of publisher and
+        // subscriber are synchronized
+        self.latency = source_timestamp.and_then(|timestamp| {
+            let sent =
+                UNIX_EPOCH + Duration::new(timestamp.sec().try_into().ok()?, timestamp.nanosec());
+            SystemTime::now().duration_since(sent).ok()
+        });
+
+        self.window.push_back((now, frame_size));
+        while let Some((received, _)) = self.window.front() {
+            if now - *received > RATE_WINDOW {
+                self.window.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Forgets the last frame number, e.g. after switching to a layer that
+    /// numbers its frames independently, so that no frames count as dropped
+    pub fn restart_numbering(&mut self) {
+        self.last_frame_num = None;
+    }
+
+    /// Frames that were not shown in time, because the decoding queue
+    /// overflowed or the jitter buffer released them late or not at all
+    pub fn troubles(&self) -> u64 {
+        self.overflow_frames + self.late_frames + self.discarded_frames
+    }
+
+    /// Records a frame that arrived after its playout time
+    pub fn record_late_frame(&mut self) {
+        self.late_frames += 1;
+    }
+
+    /// Records a frame that the jitter buffer dropped
+    pub fn record_discarded_frame(&mut self) {
+        self.discarded_frames += 1;
+    }
+
+    /// Records a lost frame rebuilt from the parity
+    pub fn record_recovered_frame(&mut self) {
+        self.recovered_frames += 1;
+    }
+
+    /// Records lost frames the parity could not rebuild
+    pub fn record_unrecoverable_frames(&mut self, count: u64) {
+        self.unrecoverable_frames += count;
+    }
+
+    /// Records a frame given up because fragments of it were missing
+    pub fn record_incomplete_frame(&mut self) {
+        self.incomplete_frames += 1;
+    }
+
+    /// Records a frame dropped because the decoding queue of the stream was full
+    pub fn record_overflow_frame(&mut self) {
+        self.overflow_frames += 1;
+    }
+
+    pub fn record_deadline_missed(&mut self) {
+        self.deadline_missed += 1;
+    }
+
+    pub fn record_liveliness_lost(&mut self) {
+        self.liveliness_lost += 1;
+    }
+
+    pub fn user_id(&self) -> i16 {
+        self.user_id
+    }
+
+    pub fn last_frame_num(&self) -> Option<i32> {
+        self.last_frame_num
+    }
+
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    pub fn late_frames(&self) -> u64 {
+        self.late_frames
+    }
+
+    pub fn discarded_frames(&self) -> u64 {
+        self.discarded_frames
+    }
+
+    pub fn recovered_frames(&self) -> u64 {
+        self.recovered_frames
+    }
+
+    pub fn unrecoverable_frames(&self) -> u64 {
+        self.unrecoverable_frames
+    }
+
+    pub fn incomplete_frames(&self) -> u64 {
+        self.incomplete_frames
+    }
+
+    pub fn overflow_frames(&self) -> u64 {
+        self.overflow_frames
+    }
+
+    pub fn deadline_missed(&self) -> u64 {
+        self.deadline_missed
+    }
+
+    pub fn liveliness_lost(&self) -> u64 {
+        self.liveliness_lost
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.window.len() as f64 / RATE_WINDOW.as_secs_f64()
+    }
+
+    pub fn bitrate(&self) -> f64 {
+        let bytes: usize = self.window.iter().map(|(_, size)| size).sum();
+        (bytes * 8) as f64 / RATE_WINDOW.as_secs_f64()
+    }
+
+    /// Multi-line summary as shown by the statistics overlay
+    pub fn overlay_text(&self) -> String {
+        let latency = match self.latency {
+            Some(latency) => format!("{} ms", latency.as_millis()),
+            None => "unknown".to_string(),
+        };
+        format!(
+            "user_id: {}\nframe: {}\nfps: {:.1}\nbitrate: {:.0} kbit/s\nlatency: {}\ndropped: {}\nlate: {}\ndiscarded: {}\nrecovered: {}\nunrecoverable: {}\nincomplete: {}\noverflow: {}\nstalled: {}\nlost: {}",
+            self.user_id,
+            self.last_frame_num.unwrap_or_default(),
+            self.fps(),
+            self.bitrate() / 1000.0,
+            latency,
+            self.dropped_frames,
+            self.late_frames,
+            self.discarded_frames,
+            self.recovered_frames,
+            self.unrecoverable_frames,
+            self.incomplete_frames,
+            self.overflow_frames,
+            self.deadline_missed,
+            self.liveliness_lost
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(40);
+
+    #[test]
+    fn rates_cover_the_frames_of_the_last_second() {
+        let start = Instant::now();
+        let mut statistics = StreamStatistics::new(1);
+        for frame_num in 0..25 {
+            statistics.record_at(frame_num, 1000, None, start + INTERVAL * frame_num as u32);
+        }
+        assert_eq!(statistics.fps(), 25.0);
+        assert_eq!(statistics.bitrate(), 200_000.0);
+
+        // The first 10 frames fall out of the window
+        statistics.record_at(25, 6000, None, start + RATE_WINDOW + INTERVAL * 10);
+        assert_eq!(statistics.fps(), 16.0);
+        assert_eq!(statistics.bitrate(), 168_000.0);
+    }
+
+    #[test]
+    fn skipped_frame_numbers_count_as_dropped() {
+        let now = Instant::now();
+        let mut statistics = StreamStatistics::new(1);
+        for frame_num in [0, 1, 4, 5, 9] {
+            statistics.record_at(frame_num, 0, None, now);
+        }
+        assert_eq!(statistics.dropped_frames(), 5);
+        assert_eq!(statistics.last_frame_num(), Some(9));
+
+        // Reordered frames are not counted
+        statistics.record_at(8, 0, None, now);
+        assert_eq!(statistics.dropped_frames(), 5);
+    }
+
+    #[test]
+    fn frame_numbers_wrap_around() {
+        let now = Instant::now();
+        let mut statistics = StreamStatistics::new(1);
+        statistics.record_at(i32::MAX - 1, 0, None, now);
+        statistics.record_at(i32::MIN + 1, 0, None, now);
+        assert_eq!(statistics.dropped_frames(), 2);
+    }
+
+    #[test]
+    fn restarted_numbering_drops_no_frames() {
+        let now = Instant::now();
+        let mut statistics = StreamStatistics::new(1);
+        statistics.record_at(100, 0, None, now);
+        statistics.restart_numbering();
+        statistics.record_at(0, 0, None, now);
+        statistics.record_at(1, 0, None, now);
+        assert_eq!(statistics.dropped_frames(), 0);
+    }
+
+    #[test]
+    fn troubles_count_the_frames_not_shown_in_time() {
+        let mut statistics = StreamStatistics::new(1);
+        statistics.record_late_frame();
+        statistics.record_discarded_frame();
+        statistics.record_overflow_frame();
+        statistics.record_recovered_frame();
+        statistics.record_unrecoverable_frames(2);
+        assert_eq!(statistics.troubles(), 3);
+        assert_eq!(statistics.unrecoverable_frames(), 2);
+    }
+}
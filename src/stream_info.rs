@@ -0,0 +1,142 @@
+use dust_dds::{
+    publication::data_writer::DataWriter,
+    subscription::{
+        data_reader::DataReader,
+        data_reader_listener::DataReaderListener,
+        sample_info::{ANY_INSTANCE_STATE, ANY_SAMPLE_STATE, ANY_VIEW_STATE},
+    },
+};
+use gstreamer::prelude::*;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+pub const STREAM_INFO_TOPIC_NAME: &str = "StreamInfo";
+pub const STREAM_INFO_TYPE_NAME: &str = "StreamInfo";
+
+/// Description of a video stream, announced by its publisher with
+/// transient-local durability so that late joining subscribers receive it.
+#[derive(Debug, Clone, PartialEq, dust_dds::topic_definition::type_support::DdsType)]
+pub struct StreamInfo {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    pub display_name: String,
+    /// Short codec name, e.g. `h264`
+    pub codec: String,
+    /// GStreamer caps of the encoded frames
+    pub caps: String,
+    pub width: i32,
+    pub height: i32,
+    pub framerate_num: i32,
+    pub framerate_den: i32,
+    /// Target bitrate in bit/s
+    pub bitrate: u32,
+    /// Optional features offered by the publisher
+    pub capabilities: Vec<String>,
+    /// Number of temporal layers, see `Video::temporal_layer`
+    pub temporal_layers: u8,
+}
+
+impl StreamInfo {
+    pub fn new(user_id: i16, display_name: String, bitrate: u32) -> Self {
+        Self {
+            user_id,
+            display_name,
+            codec: String::new(),
+            caps: String::new(),
+            width: 0,
+            height: 0,
+            framerate_num: 0,
+            framerate_den: 1,
+            bitrate,
+            capabilities: Vec::new(),
+            temporal_layers: 1,
+        }
+    }
+
+    /// Takes the codec, resolution and framerate from the caps of the encoded stream
+    pub fn update_from_caps(&mut self, caps: &gstreamer::CapsRef) {
+        self.caps = caps.to_string();
+        if let Some(structure) = caps.structure(0) {
+            self.codec = match structure.name().as_str() {
+                "video/x-h264" => "h264".to_string(),
+                name => name.trim_start_matches("video/x-").to_string(),
+            };
+            self.width = structure.get::<i32>("width").unwrap_or_default();
+            self.height = structure.get::<i32>("height").unwrap_or_default();
+            if let Ok(framerate) = structure.get::<gstreamer::Fraction>("framerate") {
+                self.framerate_num = framerate.numer();
+                self.framerate_den = framerate.denom();
+            }
+        }
+    }
+}
+
+/// Writes the stream info every time the caps arriving at the appsink change
+pub fn announce_stream_info(
+    appsink: &gstreamer_app::AppSink,
+    writer: DataWriter<StreamInfo>,
+    stream_info: StreamInfo,
+) {
+    let sink_pad = appsink.static_pad("sink").expect("appsink has sink pad");
+    sink_pad.connect_notify(Some("caps"), move |pad, _| {
+        if let Some(caps) = pad.current_caps() {
+            let mut stream_info = stream_info.clone();
+            stream_info.update_from_caps(&caps);
+            println!(
+                "Announcing {} {}x{} ({})",
+                stream_info.codec, stream_info.width, stream_info.height, stream_info.caps
+            );
+            if let Err(e) = writer.write(&stream_info, None) {
+                println!("Writing stream info failed: {:?}", e);
+            }
+        }
+    });
+}
+
+/// Latest stream info of every `user_id`, shared between the stream info
+/// listener and the video listener
+#[derive(Clone, Default)]
+pub struct StreamInfoCache(Arc<Mutex<HashMap<i16, StreamInfo>>>);
+
+impl StreamInfoCache {
+    pub fn get(&self, user_id: i16) -> Option<StreamInfo> {
+        self.0
+            .lock()
+            .expect("stream info lock not poisoned")
+            .get(&user_id)
+            .cloned()
+    }
+
+    fn insert(&self, stream_info: StreamInfo) {
+        self.0
+            .lock()
+            .expect("stream info lock not poisoned")
+            .insert(stream_info.user_id, stream_info);
+    }
+}
+
+/// Stores all received stream infos in the cache
+pub struct StreamInfoListener {
+    pub cache: StreamInfoCache,
+}
+
+impl<'a> DataReaderListener<'a> for StreamInfoListener {
+    type Foo = StreamInfo;
+
+    fn on_data_available(&mut self, the_reader: DataReader<Self::Foo>) {
+        if let Ok(samples) = the_reader.take(
+            i32::MAX,
+            ANY_SAMPLE_STATE,
+            ANY_VIEW_STATE,
+            ANY_INSTANCE_STATE,
+        ) {
+            for sample in samples {
+                if let Ok(stream_info) = sample.data() {
+                    self.cache.insert(stream_info);
+                }
+            }
+        }
+    }
+}
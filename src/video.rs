@@ -0,0 +1,18 @@
+/// One encoded frame of a video stream.
+///
+/// `user_id` is the key of the topic, so every camera (or group of redundant
+/// cameras sharing a `user_id`) is a separate instance.
+#[derive(Debug, dust_dds::topic_definition::type_support::DdsType)]
+pub struct Video<'a> {
+    #[dust_dds(key)]
+    pub user_id: i16,
+    pub frame_num: i32,
+    /// Temporal layer of the frame, 0 being the base layer. Frames of a layer
+    /// are only predicted from the layers below it, see
+    /// `StreamInfo::temporal_layers`.
+    pub temporal_layer: u8,
+    pub frame: &'a [u8],
+}
+
+pub const VIDEO_TOPIC_NAME: &str = "VideoStream";
+pub const VIDEO_TYPE_NAME: &str = "Video";